@@ -0,0 +1,40 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use modlet::modlet::{file_map, Modlet};
+use std::fs;
+
+/// Creates `modlets` modlets, each with the same `files` config XML files
+fn create_collection(root: &std::path::Path, modlets: usize, files: usize) -> Vec<Modlet> {
+    (0..modlets)
+        .map(|m| {
+            let path = root.join(format!("Modlet{m}"));
+            for f in 0..files {
+                let file = path.join(format!("Config/dir{}/file{f}.xml", f % 10));
+                fs::create_dir_all(file.parent().unwrap()).unwrap();
+                fs::write(file, "<configs></configs>").unwrap();
+            }
+
+            Modlet::new(path).unwrap()
+        })
+        .collect()
+}
+
+fn bench_file_map(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("file_map");
+
+    for (modlets, files) in [(10, 100), (50, 200)] {
+        let root = dir.path().join(format!("{modlets}x{files}"));
+        let collection = create_collection(&root, modlets, files);
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{modlets} modlets x {files} files")),
+            &collection,
+            |b, collection| b.iter(|| file_map(collection)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_file_map);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+use super::ModletError;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The filename of the optional per-modlet packaging configuration
+pub const CONFIG_FILENAME: &str = "dmt.modlet.toml";
+
+/// Packaging hints declared by a modlet author in `dmt.modlet.toml`
+///
+/// ```toml
+/// priority = -10
+/// exclude = ["dev/debug.xml"]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModletConfig {
+    /// Modlets with a lower priority are packaged first, so later ones can override them (default: 0)
+    pub priority: i32,
+    /// Files (relative to the config directory) to leave out of the modlet
+    pub exclude: Vec<PathBuf>,
+}
+
+impl ModletConfig {
+    /// Reads the `dmt.modlet.toml` of the modlet at `path`, if it has one
+    pub fn load(path: &Path) -> Result<Self, ModletError> {
+        match super::find_entry(path, CONFIG_FILENAME).filter(|file| file.is_file()) {
+            Some(file) => toml::from_str(&fs::read_to_string(&file)?).map_err(|err| ModletError::InvalidModletConfig {
+                path: file,
+                message: err.message().to_string(),
+            }),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Returns true if `file` (relative to the config directory) is excluded
+    pub fn is_excluded(&self, file: &Path) -> bool {
+        self.exclude.iter().any(|excluded| excluded == file)
+    }
+}
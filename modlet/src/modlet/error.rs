@@ -0,0 +1,31 @@
+use std::{io, path::PathBuf};
+use thiserror::Error;
+
+/// Errors returned while loading, fixing or writing a modlet
+#[derive(Debug, Error)]
+pub enum ModletError {
+    #[error("Modlet XML {0}: file not found")]
+    FileNotFound(PathBuf),
+    #[error("Could not parse {path} (line {line}): {message}")]
+    ParseError {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("Invalid Modlet {0}: Config directory does not exist")]
+    InvalidConfigDir(PathBuf),
+    #[error("Invalid {path}: {message}")]
+    InvalidModletConfig { path: PathBuf, message: String },
+    #[error("Could not write {path}: {source}")]
+    WriteError { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+    #[error(transparent)]
+    ModinfoError(#[from] modinfo::ModinfoError),
+    #[error(transparent)]
+    XmlError(#[from] quick_xml::Error),
+    #[error(transparent)]
+    GlobError(#[from] glob::GlobError),
+    #[error(transparent)]
+    PatternError(#[from] glob::PatternError),
+}
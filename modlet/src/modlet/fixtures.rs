@@ -0,0 +1,30 @@
+//! Helpers for loading the test fixtures in `modlet/tests/fixtures`
+
+use super::Modlet;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Returns the path of the named fixture (e.g. `Stats` or `Stats/ModInfo.xml`)
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// Returns the path of the named standalone XML fixture in `tests/fixtures/xml`
+pub fn xml_fixture(name: &str) -> PathBuf {
+    fixture("xml").join(name)
+}
+
+/// Loads the named fixture modlet
+pub fn load_fixture(name: &str) -> Modlet {
+    Modlet::new(fixture(name)).unwrap()
+}
+
+/// Creates a minimal modlet in `dir`, named after the directory, whose ModInfo is written as `modinfo_name`
+pub fn temp_modlet(dir: &Path, modinfo_name: &str) {
+    let name = dir.file_name().unwrap().to_string_lossy();
+
+    fs::create_dir_all(dir.join("Config")).unwrap();
+    fs::write(dir.join(modinfo_name), format!("<xml><Name value=\"{name}\" /></xml>")).unwrap();
+}
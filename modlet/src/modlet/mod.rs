@@ -4,49 +4,96 @@ use rayon::prelude::*;
 use std::fmt;
 use std::{
     borrow::Cow,
+    collections::{BTreeMap, HashMap},
     fs::{self, File},
     io::{self, prelude::*, Write},
     path::{Path, PathBuf},
+    thread,
+    time::Duration,
 };
 
+mod error;
+pub use error::ModletError;
+
+mod config;
+pub use config::{ModletConfig, CONFIG_FILENAME};
+
 mod modlet_xml;
-use modlet_xml::ModletXML;
+pub use modlet_xml::{Command, CsvInstruction, InstructionSet, ModletXML};
+
+mod options;
+pub use options::{
+    LineEnding, LocalizationHeader, ModletOptions, RetryHook, ValidationOptions, WriteOptions, DEFAULT_IO_RETRIES,
+    MODINFO_FILENAME,
+};
+
+mod package;
+pub use package::{bundle_xml, clear_config_dir, package, package_with, read_ignore_file, IGNORE_FILENAME};
+
+mod validation;
+pub use validation::{Check, Problem, Severity};
+
+#[cfg(test)]
+mod fixtures;
 
 const INCLUDE_EXTENSIONS: [&str; 3] = ["xml", "txt", "dll"];
+// The delay before the first retry of a failed file operation; later retries wait proportionally longer
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
 
 /// Represents a modlet
 #[derive(Debug, Clone, PartialEq)]
 pub struct Modlet {
+    pub config: ModletConfig,
     pub files: Option<Vec<PathBuf>>,
     pub modinfo: Modinfo,
     pub path: PathBuf,
     pub xmls: Vec<ModletXML>,
+    modinfo_file: Option<PathBuf>,
+    // Errors from XML files which were skipped while loading (see `ModletOptions::allow_invalid_xml`)
+    load_errors: Vec<String>,
 }
 
 impl Modlet {
-    pub fn new(path: impl AsRef<Path>) -> eyre::Result<Self> {
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, ModletError> {
+        Self::with_options(path, &ModletOptions::default())
+    }
+
+    pub fn with_options(path: impl AsRef<Path>, options: &ModletOptions) -> Result<Self, ModletError> {
         let mut other_files = Vec::new();
         let path = path.as_ref().to_path_buf();
         let mut xmls = Vec::new();
-        let modinfo = if path.join("ModInfo.xml").exists() {
-            modinfo::parse(path.join("ModInfo.xml"))?
-        } else {
-            Modinfo::new()
+        let mut load_errors = Vec::new();
+        let modinfo_file = find_modinfo(&path, options.modinfo_filename());
+        let modinfo = match modinfo_file.as_ref() {
+            Some(file) => modinfo::parse(file)?,
+            None => Modinfo::new(),
         };
-        let glob_pattern = path.join("Config/**/*");
+        let config = ModletConfig::load(&path)?;
+        let config_dir = config_root(&path);
+        if !config_dir.is_dir() && !options.allow_missing_config {
+            return Err(ModletError::InvalidConfigDir(config_dir));
+        }
+
+        let glob_pattern = config_dir.join("**/*");
         for file in glob(glob_pattern.to_str().unwrap())? {
             let file = file?;
-            if file.is_dir() {
+            if file.is_dir() || config.is_excluded(file.strip_prefix(&config_dir).unwrap_or(&file)) {
                 continue;
             }
 
             let file_extension = file.extension().unwrap_or_default().to_ascii_lowercase();
-            if !INCLUDE_EXTENSIONS.contains(&file_extension.to_str().unwrap()) {
+            if !INCLUDE_EXTENSIONS.contains(&file_extension.to_str().unwrap())
+                || options.is_excluded_extension(file_extension.to_str().unwrap())
+            {
                 continue;
             }
 
             if file_extension == "xml" {
-                xmls.push(ModletXML::new(file).load()?);
+                match ModletXML::new(file).load() {
+                    Ok(xml) => xmls.push(xml),
+                    Err(err) if options.allow_invalid_xml => load_errors.push(err.to_string()),
+                    Err(err) => return Err(err),
+                }
             } else {
                 other_files.push(file);
             }
@@ -59,13 +106,31 @@ impl Modlet {
         };
 
         Ok(Self {
+            config,
             files,
             modinfo,
             path,
             xmls,
+            modinfo_file,
+            load_errors,
         })
     }
 
+    /// Returns the path to the modlet's config directory
+    ///
+    /// The directory is matched case-insensitively (`Config`, `config`, ...); if none exists, the conventional
+    /// `Config` path is returned.
+    pub fn config_root(&self) -> PathBuf {
+        config_root(&self.path)
+    }
+
+    /// Returns the path to the modlet's ModInfo file, as found on disk (its name is matched case-insensitively)
+    ///
+    /// Returns `None` if the modlet has no ModInfo file.
+    pub fn modinfo_path(&self) -> Option<&Path> {
+        self.modinfo_file.as_deref()
+    }
+
     pub fn xml_files(&self) -> Vec<Cow<Path>> {
         let mut xml_files = Vec::new();
         for xml in &self.xmls {
@@ -74,46 +139,121 @@ impl Modlet {
         xml_files
     }
 
+    /// Returns the number of commands of each type (keyed by command type name) across all XML files
+    pub fn command_counts(&self) -> HashMap<&'static str, usize> {
+        let mut counts = HashMap::new();
+        for command in self.xmls.iter().flat_map(|xml| &xml.commands) {
+            *counts.entry(command.kind()).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    /// Applies `visitor` to every command of every XML file, e.g. to rewrite xpaths or values before writing
+    pub fn visit_commands_mut<F: FnMut(&mut Command)>(&mut self, mut visitor: F) {
+        for command in self.xmls.iter_mut().flat_map(|xml| &mut xml.commands) {
+            visitor(command);
+        }
+    }
+
     /// Returns the name of the modlet
     pub fn name(&self) -> Cow<str> {
         self.path.file_name().unwrap_or_default().to_str().unwrap().into()
     }
 
     /// Write XML files
-    pub fn write_xmls(&self, writer: &mut quick_xml::Writer<impl Write>, filename: &Path) -> eyre::Result<()> {
+    pub fn write_xmls(&self, writer: &mut quick_xml::Writer<impl Write>, filename: &Path) -> Result<(), ModletError> {
+        self.write_xmls_with(writer, filename, &WriteOptions::default())
+    }
+
+    /// Write XML files as configured by `options`
+    pub fn write_xmls_with(
+        &self,
+        writer: &mut quick_xml::Writer<impl Write>,
+        filename: &Path,
+        options: &WriteOptions,
+    ) -> Result<(), ModletError> {
         self.xmls
             .iter()
-            .filter(|xml| *xml.filename() == *filename)
-            .try_for_each(|xml| xml.write(writer))?;
+            .filter(|xml| same_file(&xml.filename(), filename) && !options.is_ignored(filename))
+            .try_for_each(|xml| xml.write_with(writer, options))?;
 
         Ok(())
     }
 
+    /// Returns the modlet's localization files (any `localization.txt`, matched case-insensitively)
+    pub fn localization_files(&self) -> Vec<&PathBuf> {
+        self.files
+            .iter()
+            .flatten()
+            .filter(|file| {
+                file.file_name()
+                    .unwrap_or_default()
+                    .eq_ignore_ascii_case("localization.txt")
+            })
+            .collect()
+    }
+
     /// Write non-xml files
-    pub fn write_files(&self, destination: &Path) -> eyre::Result<()> {
+    pub fn write_files(&self, destination: &Path) -> Result<(), ModletError> {
+        self.write_files_with(destination, &WriteOptions::default())
+    }
+
+    /// Write non-xml files, retrying failed copies as configured by `options`
+    pub fn write_files_with(&self, destination: &Path, options: &WriteOptions) -> Result<(), ModletError> {
+        let localization_files = self.localization_files();
+
         if let Some(files) = self.files.as_ref() {
-            files.into_par_iter().try_for_each(|file| -> eyre::Result<()> {
+            let config_root = self.config_root();
+            files.into_par_iter().try_for_each(|file| -> Result<(), ModletError> {
+                if options.is_ignored(file.strip_prefix(&config_root).unwrap_or(file)) {
+                    return Ok(());
+                }
+
+                let is_localization = localization_files.contains(&file);
                 let file = file.strip_prefix(&self.path).unwrap();
                 let src = self.path.join(file);
                 let dst = destination.join(file);
-                if !dst.exists() {
-                    fs::create_dir_all(dst.parent().unwrap())?;
-                    fs::copy(src, dst)?;
-                // If the file is a localization file, and we've already copied it from an existing modlet above,
-                // strip the header and append the remaining lines to the existing file
-                } else if src.file_name().unwrap_or_default().to_ascii_lowercase() == "localization.txt" {
-                    let input = File::open(src)?;
-                    let reader = io::BufReader::new(input);
-                    let mut output = fs::OpenOptions::new().append(true).open(&dst)?;
-                    let mut writer = io::BufWriter::new(&mut output);
-
-                    for line in reader.lines().skip(1) {
-                        let line = line?;
-                        write!(writer, "{}\r\n", line)?; // We always write localization files with CRLF
+                let write = || -> io::Result<()> {
+                    if !dst.exists() {
+                        let on_retry = |err: &io::Error, attempt| {
+                            if let Some(hook) = options.on_retry.as_ref() {
+                                hook.call(&src, err, attempt);
+                            }
+                        };
+                        with_retries(options.io_retries, on_retry, || {
+                            fs::create_dir_all(dst.parent().unwrap())
+                        })?;
+                        with_retries(options.io_retries, on_retry, || fs::copy(&src, &dst))?;
+                    // If the file is a localization file, and we've already copied it from an existing modlet above,
+                    // handle its header (see `LocalizationHeader`) and append the remaining lines to the existing file
+                    } else if is_localization {
+                        let mut lines = io::BufReader::new(File::open(&src)?).lines();
+                        let header = match (options.localization_header, lines.next().transpose()?) {
+                            (_, None) | (LocalizationHeader::Skip, _) => None,
+                            (LocalizationHeader::Comment, Some(header)) => Some(format!("# {header}")),
+                            (LocalizationHeader::KeepFirstOnly, Some(header)) => {
+                                let existing = io::BufReader::new(File::open(&dst)?).lines().next().transpose()?;
+                                (existing.as_deref() != Some(header.as_str())).then_some(header)
+                            }
+                        };
+
+                        let mut output = fs::OpenOptions::new().append(true).open(&dst)?;
+                        let mut writer = io::BufWriter::new(&mut output);
+                        let line_ending = options.localization_line_ending.as_str();
+
+                        for line in header.into_iter().map(Ok).chain(lines) {
+                            write!(writer, "{}{line_ending}", line?)?;
+                        }
                     }
-                }
 
-                Ok(())
+                    Ok(())
+                };
+
+                write().map_err(|source| ModletError::WriteError {
+                    path: dst.clone(),
+                    source,
+                })
             })?;
         }
 
@@ -121,8 +261,359 @@ impl Modlet {
     }
 }
 
+/// Runs a (transiently failing) IO operation, retrying it up to `retries` times with a short backoff
+///
+/// Only transient errors (see [`is_transient`]) are retried, and `on_retry` is called with the error and attempt number
+/// before each retry; once the retries are exhausted the last error is returned.
+fn with_retries<T>(
+    retries: u32,
+    on_retry: impl Fn(&io::Error, u32),
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                attempt += 1;
+                on_retry(&err, attempt);
+                thread::sleep(RETRY_BACKOFF * attempt);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns true if an IO error may succeed when retried (as opposed to e.g. a missing file or denied permission)
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+    )
+}
+
+/// Maps each config XML file (relative to `Config/`) to the modlets which contain it, in the order given
+///
+/// Files are matched case-insensitively (the game treats `blocks.xml` and `Blocks.xml` as the same file), and are
+/// keyed by the spelling of the first modlet which contains them.
+pub fn file_map(modlets: &[Modlet]) -> BTreeMap<PathBuf, Vec<&Modlet>> {
+    let mut files = BTreeMap::<PathBuf, Vec<&Modlet>>::new();
+    let mut spellings = HashMap::<String, PathBuf>::new();
+    for modlet in modlets {
+        for xml in &modlet.xmls {
+            let filename = xml.filename();
+            let key = spellings
+                .entry(file_key(&filename))
+                .or_insert_with(|| filename.into_owned());
+            let entry = files.entry(key.clone()).or_default();
+
+            // A modlet may contain several spellings of the same file, but is only listed once
+            if !entry.last().is_some_and(|last| std::ptr::eq(*last, modlet)) {
+                entry.push(modlet);
+            }
+        }
+    }
+
+    files
+}
+
+/// Returns true if `a` and `b` name the same config file, ignoring case
+fn same_file(a: &Path, b: &Path) -> bool {
+    file_key(a) == file_key(b)
+}
+
+fn file_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Finds the ModInfo file of the modlet at `path`, matching `filename` case-insensitively
+pub fn find_modinfo(path: &Path, filename: &str) -> Option<PathBuf> {
+    find_entry(path, filename).filter(|file| file.is_file())
+}
+
+/// Resolves the config directory of the modlet at `path`
+fn config_root(path: &Path) -> PathBuf {
+    find_entry(path, "config")
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_else(|| path.join("Config"))
+}
+
+/// Finds a directory entry of `path` whose name matches `name` case-insensitively
+fn find_entry(path: &Path, name: &str) -> Option<PathBuf> {
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .find(|entry| entry.file_name().unwrap_or_default().eq_ignore_ascii_case(name))
+}
+
 impl fmt::Display for Modlet {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.name())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::{fixture, load_fixture, temp_modlet};
+
+    #[rstest]
+    #[case::with_capital_config("CapitalConfig", "Config")]
+    #[case::with_lowercase_config("LowercaseConfig", "config")]
+    fn test_config_root(#[case] name: &str, #[case] config_dir: &str) {
+        let modlet = load_fixture(name);
+
+        assert_eq!(fixture(name).join(config_dir), modlet.config_root());
+        assert_eq!(1, modlet.xmls.len());
+    }
+
+    #[test]
+    fn test_command_counts() {
+        let modlet = Modlet::new(fixture("Stats")).unwrap();
+
+        assert_eq!(
+            HashMap::from([
+                ("append", 1),
+                ("comment", 1),
+                ("remove", 1),
+                ("set", 2),
+                ("starttag", 1)
+            ]),
+            modlet.command_counts()
+        );
+    }
+
+    #[test]
+    fn test_localization_files() {
+        let modlet = Modlet::new(fixture("Localized")).unwrap();
+        let mut localization_files = modlet.localization_files();
+        localization_files.sort();
+
+        assert_eq!(
+            vec![
+                &fixture("Localized").join("Config/Localization.txt"),
+                &fixture("Localized").join("Config/quests/localization.txt"),
+            ],
+            localization_files
+        );
+    }
+
+    #[test]
+    fn test_missing_config() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::copy(
+            fixture("CapitalConfig").join("ModInfo.xml"),
+            dir.path().join("ModInfo.xml"),
+        )
+        .unwrap();
+
+        let err = Modlet::new(dir.path()).unwrap_err();
+        assert!(matches!(err, ModletError::InvalidConfigDir(_)));
+        assert!(err.to_string().ends_with("Config directory does not exist"));
+
+        let options = ModletOptions::new().with_allow_missing_config(true);
+        let modlet = Modlet::with_options(dir.path(), &options).unwrap();
+        assert!(modlet.xmls.is_empty());
+        assert!(modlet.files.is_none());
+        assert!(!modlet.is_valid());
+    }
+
+    #[rstest]
+    #[case::with_skip(LocalizationHeader::Skip, LineEnding::Crlf, "Key,english\r\nB,b\r\n", "B,b\r\n")]
+    #[case::with_comment(
+        LocalizationHeader::Comment,
+        LineEnding::Crlf,
+        "Key,english\r\nB,b\r\n",
+        "# Key,english\r\nB,b\r\n"
+    )]
+    #[case::with_repeated_header(LocalizationHeader::KeepFirstOnly, LineEnding::Lf, "Key,english\r\nB,b\r\n", "B,b\n")]
+    #[case::with_headerless_file(LocalizationHeader::KeepFirstOnly, LineEnding::Lf, "B,b\r\nC,c\r\n", "B,b\nC,c\n")]
+    fn test_localization_merge(
+        #[case] header: LocalizationHeader,
+        #[case] line_ending: LineEnding,
+        #[case] appended: &str,
+        #[case] expected: &str,
+    ) {
+        let dir = tempfile::tempdir().unwrap();
+        let localized_modlet = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.join("Config")).unwrap();
+            fs::write(path.join("Config/Localization.txt"), content).unwrap();
+            Modlet::new(path).unwrap()
+        };
+        let existing = "Key,english\r\nA,a\r\n";
+        let destination = dir.path().join("Packaged");
+        let options = WriteOptions::new()
+            .with_localization_header(header)
+            .with_localization_line_ending(line_ending);
+
+        localized_modlet("A", existing)
+            .write_files_with(&destination, &options)
+            .unwrap();
+        localized_modlet("B", appended)
+            .write_files_with(&destination, &options)
+            .unwrap();
+
+        assert_eq!(
+            format!("{existing}{expected}"),
+            fs::read_to_string(destination.join("Config/Localization.txt")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_file_map() {
+        let modlets = [
+            Modlet::new(fixture("Localized")).unwrap(),
+            Modlet::new(fixture("Stats")).unwrap(),
+            Modlet::new(fixture("CapitalConfig")).unwrap(),
+        ];
+        let names = file_map(&modlets)
+            .into_iter()
+            .map(|(file, modlets)| (file, modlets.iter().map(|modlet| modlet.name().to_string()).collect()))
+            .collect::<Vec<(PathBuf, Vec<String>)>>();
+
+        assert_eq!(
+            vec![
+                (
+                    PathBuf::from("blocks.xml"),
+                    vec![String::from("Localized"), String::from("CapitalConfig")]
+                ),
+                (PathBuf::from("items.xml"), vec![String::from("Stats")]),
+            ],
+            names
+        );
+    }
+
+    #[test]
+    fn test_file_map_casing() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, file) in [("Lower", "blocks.xml"), ("Upper", "Blocks.xml")] {
+            fs::create_dir_all(dir.path().join(name).join("Config")).unwrap();
+            fs::write(
+                dir.path().join(name).join("Config").join(file),
+                format!(r#"<configs><set xpath="/blocks/block[@name='{name}']/@name">{name}</set></configs>"#),
+            )
+            .unwrap();
+        }
+        let modlets = [
+            Modlet::new(dir.path().join("Lower")).unwrap(),
+            Modlet::new(dir.path().join("Upper")).unwrap(),
+        ];
+
+        let files = file_map(&modlets);
+        assert_eq!(vec![&PathBuf::from("blocks.xml")], files.keys().collect::<Vec<_>>());
+        assert_eq!(2, files[Path::new("blocks.xml")].len());
+
+        let mut writer = quick_xml::Writer::new(Vec::new());
+        for modlet in &files[Path::new("blocks.xml")] {
+            modlet.write_xmls(&mut writer, Path::new("blocks.xml")).unwrap();
+        }
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(written.contains("Lower") && written.contains("Upper"), "{written}");
+    }
+
+    #[test]
+    fn test_visit_commands_mut() {
+        let mut modlet = load_fixture("Stats");
+
+        modlet.visit_commands_mut(|command| {
+            if let Command::Set(instruction) = command {
+                for value in &mut instruction.values {
+                    if let quick_xml::events::Event::Text(text) = value {
+                        let scaled = String::from_utf8_lossy(text).parse::<u32>().unwrap() * 10;
+                        *text = quick_xml::events::BytesText::new(&scaled.to_string()).into_owned();
+                    }
+                }
+            }
+        });
+
+        let mut writer = quick_xml::Writer::new(Vec::new());
+        modlet.write_xmls(&mut writer, Path::new("items.xml")).unwrap();
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(
+            written.contains(">20</set>") && written.contains(">50</set>"),
+            "{written}"
+        );
+    }
+
+    #[test]
+    fn test_modlet_config() {
+        let modlet = Modlet::new(fixture("Prioritized")).unwrap();
+
+        assert_eq!(-5, modlet.config.priority);
+        assert_eq!(vec![Path::new("items.xml")], modlet.xml_files());
+    }
+
+    #[rstest]
+    #[case::with_txt_excluded(&["bak", ".txt"], 0)]
+    #[case::with_nothing_excluded(&[], 3)]
+    fn test_exclude_extensions(#[case] extensions: &[&str], #[case] files: usize) {
+        let options = ModletOptions::new().with_exclude_extensions(extensions);
+        let modlet = Modlet::with_options(fixture("Localized"), &options).unwrap();
+
+        assert_eq!(files, modlet.files.iter().flatten().count());
+        assert_eq!(1, modlet.xmls.len());
+    }
+
+    #[test]
+    fn test_custom_modinfo_filename() {
+        let options = ModletOptions::new().with_modinfo_filename("info.xml");
+        let modlet = Modlet::with_options(fixture("CustomModinfo"), &options).unwrap();
+
+        assert_eq!(
+            Some(String::from("CustomModinfo")),
+            modlet.modinfo.get_value_for("name").map(|name| name.to_string())
+        );
+        assert!(modlet.is_valid());
+    }
+
+    #[test]
+    fn test_lowercase_modinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Lowercase");
+        temp_modlet(&path, "modinfo.xml");
+
+        let modlet = Modlet::new(&path).unwrap();
+
+        assert_eq!(Some(path.join("modinfo.xml").as_path()), modlet.modinfo_path());
+        assert_eq!("Lowercase", modlet.modinfo.get_value_for("name").unwrap().to_string());
+    }
+
+    #[rstest]
+    #[case::with_transient_failure(2, 1, io::ErrorKind::Interrupted, true, 2)]
+    #[case::with_persistent_failure(2, 3, io::ErrorKind::TimedOut, false, 3)]
+    #[case::with_no_retries(0, 1, io::ErrorKind::Interrupted, false, 1)]
+    #[case::with_permanent_failure(2, 1, io::ErrorKind::NotFound, false, 1)]
+    fn test_with_retries(
+        #[case] retries: u32,
+        #[case] failures: u32,
+        #[case] kind: io::ErrorKind,
+        #[case] succeeds: bool,
+        #[case] expected_attempts: u32,
+    ) {
+        let mut attempts = 0;
+        let retried = std::cell::Cell::new(0);
+        let result = with_retries(
+            retries,
+            |_, attempt| retried.set(attempt),
+            || {
+                attempts += 1;
+                if attempts <= failures {
+                    Err(io::Error::new(kind, "failure"))
+                } else {
+                    Ok(attempts)
+                }
+            },
+        );
+
+        assert_eq!(succeeds, result.is_ok());
+        assert_eq!(expected_attempts, attempts);
+        assert_eq!(expected_attempts - 1, retried.get());
+    }
+}
@@ -1,5 +1,10 @@
+use crate::modlet::ModletError;
 use convert_case::{Case, Casing};
-use quick_xml::events::{BytesText, Event};
+use quick_xml::{
+    escape::partial_escape,
+    events::{attributes::Attribute, BytesText, Event},
+    name::QName,
+};
 use std::{
     borrow::Cow,
     fmt::{Display, Formatter},
@@ -18,13 +23,26 @@ pub const TEXT_COMMANDS: [&str; 3] = ["csv", "set", "setattribute"];
 pub enum CsvInstruction {
     Add(char),
     Remove(char),
+    Set(char),
+    Other(String, char),
 }
 
 impl CsvInstruction {
+    pub fn new(op: &str, delim: char) -> Self {
+        match op.to_case(Case::Flat).as_str() {
+            "add" => CsvInstruction::Add(delim),
+            "remove" => CsvInstruction::Remove(delim),
+            "set" => CsvInstruction::Set(delim),
+            _ => CsvInstruction::Other(op.to_string(), delim),
+        }
+    }
+
     pub fn delim(&self) -> &char {
         match self {
             CsvInstruction::Add(delim) => delim,
             CsvInstruction::Remove(delim) => delim,
+            CsvInstruction::Set(delim) => delim,
+            CsvInstruction::Other(_, delim) => delim,
         }
     }
 
@@ -32,14 +50,25 @@ impl CsvInstruction {
         match self {
             CsvInstruction::Add(_) => "add",
             CsvInstruction::Remove(_) => "remove",
+            CsvInstruction::Set(_) => "set",
+            CsvInstruction::Other(op, _) => op,
         }
     }
 }
 
+/// An attribute as an unescaped `(key, value)` pair
+pub type RawAttribute = (Vec<u8>, Vec<u8>);
+
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct InstructionSet {
+    /// Further xpaths the instruction is applied to, after `xpath` (an append may list several targets)
+    pub additional_xpaths: Vec<Vec<u8>>,
     pub attribute: Option<Vec<u8>>,
     pub csv_op: Option<CsvInstruction>,
+    /// Write an empty command (e.g. `remove`) with an end tag instead of self-closing it, as in its source
+    pub explicit_end: bool,
+    /// Attributes not otherwise understood by the command, in their original order
+    pub extra_attributes: Vec<RawAttribute>,
     pub values: Vec<Event<'static>>,
     pub xpath: Vec<u8>,
 }
@@ -56,8 +85,188 @@ impl InstructionSet {
             .collect()
     }
 
-    fn xpath_attribute(&self) -> (&[u8], &[u8]) {
-        (b"xpath".as_ref(), self.xpath.as_slice())
+    /// Writes the text values (separated by `,`), keeping any CDATA sections as CDATA
+    fn write_text_values(&self, writer: &mut quick_xml::Writer<impl Write>) -> Result<(), ModletError> {
+        for (index, event) in self.values.iter().enumerate() {
+            if index > 0 {
+                writer.write_event(Event::Text(BytesText::from_escaped(",")))?;
+            }
+            // Text values are kept in their escaped form, so they are written back without escaping them again
+            writer.write_event(event)?;
+        }
+
+        Ok(())
+    }
+
+    fn xpath_attribute(&self) -> Attribute<'_> {
+        escaped_attribute(b"xpath", &self.xpath)
+    }
+
+    /// Returns every xpath the instruction is applied to, starting with `xpath`
+    pub fn xpaths(&self) -> impl Iterator<Item = &[u8]> {
+        std::iter::once(self.xpath.as_slice()).chain(self.additional_xpaths.iter().map(Vec::as_slice))
+    }
+
+    fn extra_attributes(&self) -> impl Iterator<Item = Attribute<'_>> {
+        self.extra_attributes
+            .iter()
+            .map(|(key, value)| escaped_attribute(key, value))
+    }
+
+    /// Returns the number of elements added directly by the instruction (nested elements and text are not counted)
+    pub fn child_count(&self) -> usize {
+        let mut depth = 0;
+        let mut count = 0;
+        for event in &self.values {
+            match event {
+                Event::Start(_) => {
+                    if depth == 0 {
+                        count += 1;
+                    }
+                    depth += 1;
+                }
+                Event::End(_) => depth -= 1,
+                Event::Empty(_) if depth == 0 => count += 1,
+                _ => (),
+            }
+        }
+
+        count
+    }
+
+    /// Checks that the accumulated content forms balanced XML, describing the first problem found
+    pub fn check_well_formed(&self) -> Result<(), String> {
+        let mut open = Vec::new();
+        for event in &self.values {
+            match event {
+                Event::Start(element) => open.push(element.name().as_ref().to_vec()),
+                Event::End(element) => match open.pop() {
+                    Some(name) if name == element.name().as_ref() => (),
+                    Some(name) => {
+                        return Err(format!(
+                            "expected </{}> but found </{}>",
+                            String::from_utf8_lossy(&name),
+                            String::from_utf8_lossy(element.name().as_ref())
+                        ))
+                    }
+                    None => {
+                        return Err(format!(
+                            "unexpected </{}>",
+                            String::from_utf8_lossy(element.name().as_ref())
+                        ))
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        match open.last() {
+            Some(name) => Err(format!("<{}> is never closed", String::from_utf8_lossy(name))),
+            None => Ok(()),
+        }
+    }
+
+    /// Best-effort check for content containing an element that matches the final step of the xpath
+    ///
+    /// Only the element name and any `[@attr='value']` predicates of the last step are compared, so this can
+    /// neither prove nor rule out a match -- it is meant for linting.
+    fn matches_own_content(&self) -> bool {
+        let xpath = str::from_utf8(&self.xpath).unwrap_or_default();
+        let step = xpath.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+        let (name, predicates) = step.split_once('[').unwrap_or((step, ""));
+        let name = name.trim();
+
+        if name.is_empty() || name == "*" || name.starts_with('@') || name.contains(['(', ':']) {
+            return false;
+        }
+
+        let attributes = predicates
+            .split('[')
+            .filter_map(|predicate| {
+                let (key, value) = predicate
+                    .trim_end_matches(']')
+                    .trim()
+                    .strip_prefix('@')?
+                    .split_once('=')?;
+                Some((key.trim(), value.trim().trim_matches(['\'', '"'])))
+            })
+            .collect::<Vec<_>>();
+
+        self.values.iter().any(|event| match event {
+            Event::Start(element) | Event::Empty(element) => {
+                element.name().as_ref() == name.as_bytes()
+                    && attributes.iter().all(|(key, value)| {
+                        element
+                            .try_get_attribute(*key)
+                            .ok()
+                            .flatten()
+                            .is_some_and(|attribute| attribute.value.as_ref() == value.as_bytes())
+                    })
+            }
+            _ => false,
+        })
+    }
+}
+
+/// Splits an xpath attribute listing several targets (separated by `,` or `|`) into its individual xpaths
+///
+/// Separators inside predicates, function calls or quoted strings (e.g. `contains(@name, 'a')`) don't split the
+/// xpath, and an xpath without separators is returned unchanged.
+pub fn split_xpaths(xpath: &[u8]) -> Vec<Vec<u8>> {
+    let trimmed = |xpath: &[u8]| String::from_utf8_lossy(xpath).trim().as_bytes().to_vec();
+    let mut xpaths = Vec::new();
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut start = 0;
+
+    for (index, &byte) in xpath.iter().enumerate() {
+        match (quote, byte) {
+            (Some(open), _) if byte == open => quote = None,
+            (Some(_), _) => (),
+            (None, b'\'' | b'"') => quote = Some(byte),
+            (None, b'[' | b'(') => depth += 1,
+            (None, b']' | b')') => depth = depth.saturating_sub(1),
+            (None, b',' | b'|') if depth == 0 => {
+                xpaths.push(trimmed(&xpath[start..index]));
+                start = index + 1;
+            }
+            _ => (),
+        }
+    }
+    xpaths.push(trimmed(&xpath[start..]));
+    xpaths.retain(|xpath| !xpath.is_empty());
+
+    if xpaths.is_empty() {
+        vec![xpath.to_vec()]
+    } else {
+        xpaths
+    }
+}
+
+/// Makes text safe to write within a comment, which may neither contain `--` nor end with `-`
+fn comment_safe(comment: &str) -> String {
+    let mut safe = String::with_capacity(comment.len());
+
+    for ch in comment.chars() {
+        if ch == '-' && safe.ends_with('-') {
+            safe.push(' ');
+        }
+        safe.push(ch);
+    }
+    if safe.ends_with('-') {
+        safe.push(' ');
+    }
+
+    safe
+}
+
+/// Builds an attribute from an unescaped value, escaping it for use within double quotes
+fn escaped_attribute<'a>(key: &'a [u8], value: &[u8]) -> Attribute<'a> {
+    let value = partial_escape(str::from_utf8(value).unwrap_or_default()).replace('"', "&quot;");
+
+    Attribute {
+        key: QName(key),
+        value: Cow::Owned(value.into_bytes()),
     }
 }
 
@@ -75,7 +284,8 @@ pub enum Command {
     Set(InstructionSet),
     SetAttribute(InstructionSet),
     StartTag(Option<String>),
-    Unknown(Cow<'static, str>),
+    /// An unrecognised (e.g. misspelled) command, whose content is kept verbatim, including its own tags
+    Unknown(Cow<'static, str>, InstructionSet),
 }
 
 impl Command {
@@ -93,7 +303,7 @@ impl Command {
             "set" => Command::Set(InstructionSet::new()),
             "setattribute" => Command::SetAttribute(InstructionSet::new()),
             "starttag" => Command::StartTag(None),
-            tag => Command::Unknown(Cow::Owned(tag.to_string())),
+            tag => Command::Unknown(Cow::Owned(tag.to_string()), InstructionSet::new()),
         }
     }
 
@@ -110,60 +320,80 @@ impl Command {
             Command::Set(_) => Self::Set(instruction_set),
             Command::SetAttribute(_) => Self::SetAttribute(instruction_set),
             Command::StartTag(_) => Self::StartTag(None),
-            Command::Unknown(tag) => Self::Unknown(tag),
+            Command::Unknown(tag, _) => Self::Unknown(tag, instruction_set),
         }
     }
 
-    pub fn write(&self, writer: &mut quick_xml::Writer<impl Write>) -> eyre::Result<()> {
+    pub fn write(&self, writer: &mut quick_xml::Writer<impl Write>) -> Result<(), ModletError> {
         match self {
             Command::Append(is) | Command::InsertAfter(is) | Command::InsertBefore(is) => {
-                writer
-                    .create_element(&self.to_string())
-                    .with_attribute(is.xpath_attribute())
-                    .write_inner_content(move |writer| {
-                        for event in &is.values {
-                            writer.write_event(event)?;
-                        }
-                        Ok::<(), eyre::Error>(())
-                    })?;
+                // Each target gets its own element, sharing the same content
+                for xpath in is.xpaths() {
+                    writer
+                        .create_element(&self.to_string())
+                        .with_attribute(escaped_attribute(b"xpath", xpath))
+                        .with_attributes(is.extra_attributes())
+                        .write_inner_content(move |writer| {
+                            for event in &is.values {
+                                writer.write_event(event)?;
+                            }
+                            Ok::<(), ModletError>(())
+                        })?;
+                }
             }
             Command::Comment(comment) => {
-                let comment = BytesText::from_escaped(comment.clone());
+                // Comment content is written verbatim (entities are not decoded in comments)
+                let comment = BytesText::from_escaped(comment_safe(comment));
                 writer.write_event(Event::Comment(comment))?
             }
             Command::Csv(is) => {
-                writer
-                    .create_element(&self.to_string())
-                    .with_attributes([
-                        is.xpath_attribute(),
-                        (
-                            b"delim".as_ref(),
-                            is.csv_op.as_ref().unwrap().delim().to_string().as_bytes(),
-                        ),
-                        (b"op".as_ref(), is.csv_op.as_ref().unwrap().op().as_bytes()),
-                    ])
-                    .write_text_content(BytesText::new(is.values_to_strings().join(",").as_ref()))?;
+                let name = self.to_string();
+                let mut element = writer.create_element(&name).with_attribute(is.xpath_attribute());
+
+                if let Some(csv_op) = is.csv_op.as_ref() {
+                    element = element.with_attributes([
+                        escaped_attribute(b"delim", csv_op.delim().to_string().as_bytes()),
+                        escaped_attribute(b"op", csv_op.op().as_bytes()),
+                    ]);
+                }
+                element = element.with_attributes(is.extra_attributes());
+
+                element.write_inner_content(|writer| is.write_text_values(writer))?;
             }
             Command::Remove(is) | Command::RemoveAttribute(is) => {
-                writer
-                    .create_element(&self.to_string())
+                let name = self.to_string();
+                let element = writer
+                    .create_element(&name)
                     .with_attribute(is.xpath_attribute())
-                    .write_empty()?;
+                    .with_attributes(is.extra_attributes());
+
+                if is.explicit_end {
+                    element.write_inner_content(|_| Ok::<(), ModletError>(()))?;
+                } else {
+                    element.write_empty()?;
+                }
             }
             Command::Set(is) => {
                 writer
                     .create_element(&self.to_string())
                     .with_attribute(is.xpath_attribute())
-                    .write_text_content(BytesText::new(is.values_to_strings().join(",").as_ref()))?;
+                    .with_attributes(is.extra_attributes())
+                    .write_inner_content(|writer| is.write_text_values(writer))?;
             }
             Command::SetAttribute(is) => {
                 writer
                     .create_element(&self.to_string())
                     .with_attributes([
                         is.xpath_attribute(),
-                        (b"name".as_ref(), is.attribute.as_ref().unwrap().to_vec().as_slice()),
+                        escaped_attribute(b"name", is.attribute.as_deref().unwrap_or_default()),
                     ])
-                    .write_text_content(BytesText::new(is.values_to_strings().join(",").as_ref()))?;
+                    .with_attributes(is.extra_attributes())
+                    .write_inner_content(|writer| is.write_text_values(writer))?;
+            }
+            Command::Unknown(_, is) => {
+                for event in &is.values {
+                    writer.write_event(event)?;
+                }
             }
             Command::StartTag(_) => (),
             _ => (),
@@ -171,10 +401,51 @@ impl Command {
 
         Ok(())
     }
-}
 
-impl AsRef<str> for Command {
-    fn as_ref(&self) -> &str {
+    /// Returns the command as XML indented by nesting level, e.g. for showing it in diffs and messages
+    pub fn pretty(&self) -> String {
+        let mut writer = quick_xml::Writer::new_with_indent(Vec::new(), b' ', 2);
+        self.write(&mut writer).expect("writing to a Vec cannot fail");
+
+        String::from_utf8_lossy(&writer.into_inner()).into_owned()
+    }
+
+    /// Returns the instruction set of commands which operate on an xpath
+    pub fn instruction_set(&self) -> Option<&InstructionSet> {
+        match self {
+            Command::Append(is)
+            | Command::Csv(is)
+            | Command::InsertAfter(is)
+            | Command::InsertBefore(is)
+            | Command::Remove(is)
+            | Command::RemoveAttribute(is)
+            | Command::Set(is)
+            | Command::SetAttribute(is) => Some(is),
+            _ => None,
+        }
+    }
+
+    /// Returns true if an insert or append adds content which looks like it matches its own xpath target
+    ///
+    /// e.g. `<insertBefore xpath="/items/item[@name='gun']"><item name="gun" /></insertBefore>`
+    pub fn is_self_referential(&self) -> bool {
+        match self {
+            Command::Append(is) | Command::InsertAfter(is) | Command::InsertBefore(is) => is.matches_own_content(),
+            _ => false,
+        }
+    }
+
+    /// Returns the attributes the command understands itself; any others are kept as extra attributes
+    pub fn known_attributes(&self) -> &'static [&'static str] {
+        match self {
+            Command::Csv(_) => &["xpath", "delim", "op"],
+            Command::SetAttribute(_) => &["xpath", "name"],
+            _ => &["xpath"],
+        }
+    }
+
+    /// Returns the type name of the command (e.g. `append`)
+    pub fn kind(&self) -> &'static str {
         match self {
             Command::Append(_) => "append",
             Command::Comment(_) => "comment",
@@ -187,11 +458,17 @@ impl AsRef<str> for Command {
             Command::Set(_) => "set",
             Command::SetAttribute(_) => "setattribute",
             Command::StartTag(_) => "starttag",
-            Command::Unknown(_) => "unknown",
+            Command::Unknown(..) => "unknown",
         }
     }
 }
 
+impl AsRef<str> for Command {
+    fn as_ref(&self) -> &str {
+        self.kind()
+    }
+}
+
 impl Display for Command {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -206,7 +483,7 @@ impl Display for Command {
             Command::Set(_) => write!(f, "set"),
             Command::SetAttribute(_) => write!(f, "setAttribute"),
             Command::StartTag(_) => write!(f, "start_tag"),
-            Command::Unknown(tag) => write!(f, "{tag}"),
+            Command::Unknown(tag, _) => write!(f, "{tag}"),
         }
     }
 }
@@ -225,8 +502,128 @@ mod tests {
     #[case::with_append("append", Command::Append(instruction_set()))]
     #[case::with_comment("comment", Command::Comment(Cow::Owned(String::new())))]
     #[case::with_csv("csv", Command::Csv(instruction_set()))]
-    #[case::with_unknown("foo", Command::Unknown(Cow::Owned("foo".to_string())))]
+    #[case::with_unknown("foo", Command::Unknown(Cow::Owned("foo".to_string()), instruction_set()))]
     fn test_parse(#[case] input: &str, #[case] expected: Command) {
         assert_eq!(expected, Command::parse(input));
     }
+
+    #[rstest]
+    #[case::with_add("add", CsvInstruction::Add(','))]
+    #[case::with_remove("remove", CsvInstruction::Remove(','))]
+    #[case::with_set("set", CsvInstruction::Set(','))]
+    #[case::with_other("replace", CsvInstruction::Other("replace".to_string(), ','))]
+    fn test_csv_instruction(#[case] op: &str, #[case] expected: CsvInstruction) {
+        let instruction = CsvInstruction::new(op, ',');
+
+        assert_eq!(expected, instruction);
+        assert_eq!(op, instruction.op());
+    }
+
+    fn events(content: &str) -> Vec<Event<'static>> {
+        let mut reader = quick_xml::Reader::from_str(content);
+        reader.check_end_names(false);
+        let mut values = Vec::new();
+        loop {
+            match reader.read_event().unwrap() {
+                Event::Eof => break,
+                event => values.push(event.into_owned()),
+            }
+        }
+
+        values
+    }
+
+    fn insert(command: &str, xpath: &str, content: &str) -> Command {
+        Command::parse(command).set(InstructionSet {
+            values: events(content),
+            xpath: xpath.as_bytes().to_vec(),
+            ..Default::default()
+        })
+    }
+
+    #[rstest]
+    #[case::with_same_element("insertBefore", "/a", "<a/>", true)]
+    #[case::with_matching_predicate("insertAfter", "/items/item[@name='gun']", r#"<item name="gun"></item>"#, true)]
+    #[case::with_other_predicate("insertAfter", "/items/item[@name='gun']", r#"<item name="knife"/>"#, false)]
+    #[case::with_child_elements("append", "/items", r#"<item name="gun"/>"#, false)]
+    #[case::with_non_insert_command("remove", "/a", "<a/>", false)]
+    fn test_is_self_referential(
+        #[case] command: &str,
+        #[case] xpath: &str,
+        #[case] content: &str,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(expected, insert(command, xpath, content).is_self_referential());
+    }
+
+    #[rstest]
+    #[case::with_balanced_content("<item><property/></item>", Ok(()))]
+    #[case::with_unclosed_child("<item><property>", Err(String::from("<property> is never closed")))]
+    fn test_check_well_formed(#[case] content: &str, #[case] expected: Result<(), String>) {
+        let instruction = InstructionSet {
+            values: events(content),
+            ..Default::default()
+        };
+        assert_eq!(expected, instruction.check_well_formed());
+    }
+
+    #[rstest]
+    #[case::with_empty_children(r#"<item name="a"/><item name="b"/>"#, 2)]
+    #[case::with_nested_children(r#"<item name="a"><property name="Weight" value="1"/></item><!-- b --><item/>"#, 2)]
+    #[case::with_text_only("some text", 0)]
+    fn test_child_count(#[case] content: &str, #[case] expected: usize) {
+        let instruction = InstructionSet {
+            values: events(content),
+            ..Default::default()
+        };
+
+        assert_eq!(expected, instruction.child_count());
+    }
+
+    #[test]
+    fn test_pretty() {
+        let command = insert(
+            "append",
+            "/items",
+            r#"<item name="gun"><property name="Weight" value="1"/><property class="Action0"><property name="Delay" value="2"/></property></item>"#,
+        );
+
+        assert_eq!(
+            [
+                r#"<append xpath="/items">"#,
+                r#"  <item name="gun">"#,
+                r#"    <property name="Weight" value="1"/>"#,
+                r#"    <property class="Action0">"#,
+                r#"      <property name="Delay" value="2"/>"#,
+                r#"    </property>"#,
+                r#"  </item>"#,
+                r#"</append>"#,
+            ]
+            .join("\n"),
+            command.pretty()
+        );
+    }
+
+    #[rstest]
+    #[case::with_plain_text(" Included from MyMod ", " Included from MyMod ")]
+    #[case::with_double_dash("a--b", "a- -b")]
+    #[case::with_triple_dash(" Included from Mod---Beta ", " Included from Mod- - -Beta ")]
+    #[case::with_trailing_dash("ends with -", "ends with - ")]
+    fn test_comment_safe(#[case] comment: &str, #[case] expected: &str) {
+        assert_eq!(expected, comment_safe(comment));
+    }
+
+    #[rstest]
+    #[case::with_single_xpath("/items/item[@name='gun']", vec!["/items/item[@name='gun']"])]
+    #[case::with_pipes("/items/item[@name='a'] | /items/item[@name='b']", vec!["/items/item[@name='a']", "/items/item[@name='b']"])]
+    #[case::with_commas("/a,/b", vec!["/a", "/b"])]
+    #[case::with_nested_separators("/items/item[contains(@name, 'a|b')]", vec!["/items/item[contains(@name, 'a|b')]"])]
+    fn test_split_xpaths(#[case] xpath: &str, #[case] expected: Vec<&str>) {
+        let expected = expected
+            .into_iter()
+            .map(|xpath| xpath.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, split_xpaths(xpath.as_bytes()));
+    }
 }
@@ -1,18 +1,21 @@
 /// This module contains the implementation of the `ModletXML` struct and related types.
 /// The `ModletXML` struct represents an XML file containing modlet instructions.
 /// It provides methods for loading the XML file and extracting the commands from it.
-use eyre::eyre;
+use super::{ModletError, WriteOptions};
 use quick_xml::{events::Event, reader::Reader};
 use std::{
     borrow::Cow,
     collections::VecDeque,
-    io::Write,
+    error::Error,
+    fs,
+    io::{BufRead, Write},
     path::{Path, PathBuf},
     str::{self},
 };
 
 mod command;
-use command::{Command, CsvInstruction, InstructionSet};
+use command::RawAttribute;
+pub use command::{Command, CsvInstruction, InstructionSet};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct ModletXML {
@@ -21,9 +24,9 @@ pub struct ModletXML {
 }
 
 impl ModletXML {
-    pub fn load(mut self) -> eyre::Result<Self> {
+    pub fn load(mut self) -> Result<Self, ModletError> {
         if !self.path.exists() {
-            return Err(eyre!("Modlet XML {}: file not found", self.path.display()));
+            return Err(ModletError::FileNotFound(self.path));
         }
         self.commands = load_xml(self.path.as_ref())?;
 
@@ -37,29 +40,129 @@ impl ModletXML {
         }
     }
 
+    /// Returns the path of the file relative to the (outermost) `Config` directory, without allocating
     pub fn filename(&self) -> Cow<Path> {
         self.path
-            .iter()
-            .skip_while(|&ancestor| ancestor.to_ascii_lowercase() != "config")
-            .skip(1)
-            .collect::<PathBuf>()
+            .ancestors()
+            .filter(|ancestor| {
+                ancestor
+                    .file_name()
+                    .is_some_and(|name| name.eq_ignore_ascii_case("config"))
+            })
+            .last()
+            .and_then(|config| self.path.strip_prefix(config).ok())
+            .unwrap_or(Path::new(""))
             .into()
     }
 
-    pub fn write(&self, writer: &mut quick_xml::Writer<impl Write>) -> eyre::Result<()> {
-        self.commands.iter().try_for_each(|command| command.write(writer))?;
+    pub fn write(&self, writer: &mut quick_xml::Writer<impl Write>) -> Result<(), ModletError> {
+        self.write_with(writer, &WriteOptions::default())
+    }
+
+    /// Writes the commands, leaving out comments unless `options` includes them
+    pub fn write_with(
+        &self,
+        writer: &mut quick_xml::Writer<impl Write>,
+        options: &WriteOptions,
+    ) -> Result<(), ModletError> {
+        self.commands
+            .iter()
+            .filter(|command| options.include_comments || !matches!(command, Command::Comment(_)))
+            .try_for_each(|command| command.write(writer))?;
 
         Ok(())
     }
 }
 
-fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
-    let mut commands = Vec::new();
+fn load_xml(path: &Path) -> Result<Vec<Command>, ModletError> {
+    let mut reader = Reader::from_file(path)?;
+
+    let commands = read_commands(&mut reader).map_err(|err| ModletError::ParseError {
+        path: path.to_path_buf(),
+        line: line_at(path, reader.buffer_position()),
+        message: err.to_string(),
+    })?;
+    check_tag_balance(path)?;
+
+    Ok(commands)
+}
+
+/// Checks that every start tag in the file at `path` is closed by a matching end tag
+///
+/// Command parsing only tracks one command at a time, so this is a separate pass over the whole document which
+/// catches e.g. a root element which is never closed.
+fn check_tag_balance(path: &Path) -> Result<(), ModletError> {
     let mut reader = Reader::from_file(path)?;
+    reader.check_end_names(false);
+    reader.trim_markup_names_in_closing_tags(true);
+
+    let mut open = Vec::<(Vec<u8>, usize)>::new();
+    let mut buf = Vec::new();
+    let error = |position: usize, message: String| ModletError::ParseError {
+        path: path.to_path_buf(),
+        line: line_at(path, position),
+        message,
+    };
+
+    loop {
+        let position = reader.buffer_position();
+
+        match reader.read_event_into(&mut buf) {
+            Err(err) => return Err(error(reader.buffer_position(), err.to_string())),
+            Ok(Event::Start(event)) => open.push((event.name().as_ref().to_vec(), position)),
+            Ok(Event::End(event)) => {
+                let name = String::from_utf8_lossy(event.name().as_ref()).to_string();
+
+                match open.pop() {
+                    Some((expected, _)) if expected == event.name().as_ref() => {}
+                    Some((expected, opened_at)) => {
+                        return Err(error(
+                            position,
+                            format!(
+                                "</{name}> does not close <{}> (opened on line {})",
+                                String::from_utf8_lossy(&expected),
+                                line_at(path, opened_at)
+                            ),
+                        ))
+                    }
+                    None => return Err(error(position, format!("</{name}> has no matching start tag"))),
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+        }
+
+        buf.clear();
+    }
+
+    match open.pop() {
+        Some((name, opened_at)) => Err(error(
+            opened_at,
+            format!("<{}> is never closed", String::from_utf8_lossy(&name)),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns the (1-based) line number of a byte position within the file at `path`
+fn line_at(path: &Path, position: usize) -> usize {
+    let content = fs::read(path).unwrap_or_default();
+
+    content[..position.min(content.len().saturating_sub(1))]
+        .iter()
+        .filter(|&&byte| byte == b'\n')
+        .count()
+        + 1
+}
+
+fn read_commands(reader: &mut Reader<impl BufRead>) -> Result<Vec<Command>, Box<dyn Error + Send + Sync>> {
+    let mut commands = Vec::new();
     let mut stack = VecDeque::<Command>::new();
     // The modlet we're building
     let mut instruction = InstructionSet::new();
     let mut start_tag = String::new();
+    // How deeply nested we are inside the content of an unknown command
+    let mut unknown_depth = 0usize;
     let mut buf = Vec::new();
 
     // Set options on Reader
@@ -70,14 +173,16 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
         let last_command = stack.get(0).unwrap_or(&Command::NoOp).as_ref();
 
         match reader.read_event_into(&mut buf) {
-            Err(event) => panic!("Error at position {}: {:?}", reader.buffer_position(), event),
+            Err(err) => return Err(err.into()),
 
             // Found a comment
             Ok(Event::Comment(event)) => {
-                // let event = event.into_owned();
-                let comment = event.unescape().unwrap_or_default().to_string();
+                // Entities are not decoded inside comments, so we keep the content verbatim
+                let comment = str::from_utf8(&event)?.to_string();
 
-                if !comment.is_empty() {
+                if last_command == "unknown" {
+                    instruction.values.push(Event::Comment(event.into_owned()));
+                } else if !comment.is_empty() {
                     commands.push(Command::Comment(Cow::Owned(comment)));
                 }
             }
@@ -94,7 +199,17 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
                     command = Command::StartTag(Some(tag_name.to_string()));
                 }
 
-                if command::COLLECTION_COMMANDS.contains(&last_command) {
+                if last_command == "unknown" {
+                    unknown_depth += 1;
+                    instruction.values.push(Event::Start(event));
+                } else if command::COLLECTION_COMMANDS.contains(&last_command) {
+                    instruction.values.push(Event::Start(event));
+                } else if command.as_ref() == "unknown" && last_command == "noop" {
+                    // An unknown (e.g. misspelled) command; it's kept verbatim, including its own tags
+                    stack.push_back(Command::Unknown(
+                        Cow::Owned(tag_name.to_string()),
+                        InstructionSet::new(),
+                    ));
                     instruction.values.push(Event::Start(event));
                 } else if command.as_ref() != "unknown" && command.as_ref() != "noop" {
                     // println!("[STARTING] tag {:?} ({command})", str::from_utf8(e.name().as_ref()).unwrap());
@@ -104,20 +219,23 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
                         continue;
                     }
 
-                    let my_char = str::from_utf8(get_attribute(&event, "delim").unwrap_or(vec![b',']).as_ref())
-                        .unwrap()
-                        .to_string();
-                    let delim: char = my_char.chars().next().unwrap();
+                    let delim = get_attribute(&event, "delim").unwrap_or(vec![b',']);
+                    let delim = str::from_utf8(&delim)?.chars().next().unwrap_or(',');
 
-                    instruction.xpath = get_attribute(&event, "xpath").unwrap();
+                    instruction.xpath = get_attribute(&event, "xpath").unwrap_or_default();
+                    if command.kind() == "append" {
+                        let mut xpaths = command::split_xpaths(&instruction.xpath).into_iter();
+                        instruction.xpath = xpaths.next().unwrap_or_default();
+                        instruction.additional_xpaths = xpaths.collect();
+                    }
                     instruction.csv_op = match get_attribute(&event, "op") {
-                        Some(op) => match str::from_utf8(&op).unwrap() {
-                            "add" => Some(CsvInstruction::Add(delim)),
-                            "remove" => Some(CsvInstruction::Remove(delim)),
-                            _ => None,
-                        },
+                        Some(op) => Some(CsvInstruction::new(str::from_utf8(&op)?, delim)),
                         None => None,
                     };
+                    if command.kind() == "setattribute" {
+                        instruction.attribute = get_attribute(&event, "name");
+                    }
+                    instruction.extra_attributes = extra_attributes(&event, command.known_attributes())?;
                     stack.push_back(command);
                 }
             }
@@ -129,10 +247,28 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
                 let tag_name = str::from_utf8(tag_name.as_ref())?;
                 let value = str::from_utf8(event.as_ref())?;
 
-                if command::EMPTY_COMMANDS.contains(&tag_name) || command::COLLECTION_COMMANDS.contains(&last_command) {
+                let command = Command::parse(tag_name);
+
+                if last_command == "unknown" || command::COLLECTION_COMMANDS.contains(&last_command) {
                     instruction.values.push(Event::Empty(event));
+                } else if last_command == "noop" && command::EMPTY_COMMANDS.contains(&command.as_ref()) {
+                    // A self-closed command, e.g. <remove xpath="..." />
+                    let instruction = InstructionSet {
+                        xpath: get_attribute(&event, "xpath").unwrap_or_default(),
+                        extra_attributes: extra_attributes(&event, command.known_attributes())?,
+                        ..Default::default()
+                    };
+                    commands.push(command.set(instruction));
+                } else if last_command == "noop" && command.as_ref() == "unknown" {
+                    let instruction = InstructionSet {
+                        values: vec![Event::Empty(event.clone())],
+                        ..Default::default()
+                    };
+                    commands.push(Command::Unknown(Cow::Owned(tag_name.to_string()), instruction));
+                } else if last_command == "noop" && command::TEXT_COMMANDS.contains(&command.as_ref()) {
+                    return Err(format!("<{value}/> requires a value").into());
                 } else {
-                    panic!("Unhandled empty tag received: {value}");
+                    return Err(format!("Unexpected <{value}/> outside of an append or insert command").into());
                 }
             }
 
@@ -142,10 +278,32 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
                 let value = str::from_utf8(&event)?;
                 let value = value.to_string();
 
-                if command::TEXT_COMMANDS.contains(&last_command) {
+                // Entity references are kept verbatim, but unknown ones are rejected here rather than written out
+                event.unescape()?;
+
+                if command::TEXT_COMMANDS.contains(&last_command)
+                    || command::COLLECTION_COMMANDS.contains(&last_command)
+                    || last_command == "unknown"
+                {
                     instruction.values.push(Event::Text(event));
+                } else if last_command == "noop" {
+                    return Err(format!("Unexpected text \"{value}\" outside of a command").into());
                 } else {
-                    panic!("Unhandled text tag received: {value} for {last_command}");
+                    return Err(format!("Unexpected text \"{value}\" in a {last_command} command").into());
+                }
+            }
+
+            // Found a CDATA section, which is kept (and written back) as-is
+            Ok(Event::CData(event)) => {
+                if command::TEXT_COMMANDS.contains(&last_command)
+                    || command::COLLECTION_COMMANDS.contains(&last_command)
+                    || last_command == "unknown"
+                {
+                    instruction.values.push(Event::CData(event.into_owned()));
+                } else {
+                    return Err(
+                        "Unexpected CDATA section outside of a set, setattribute, csv or append command".into(),
+                    );
                 }
             }
 
@@ -155,28 +313,53 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
                 let tag_name = str::from_utf8(event.as_ref())?;
                 let mut command = Command::parse(tag_name);
 
-                if command.as_ref() == "unknown" && !start_tag.is_empty() {
-                    command = Command::StartTag(Some(start_tag.to_string()));
-                }
-
-                if command::COLLECTION_COMMANDS.contains(&last_command) && command.as_ref() != last_command {
+                if last_command == "unknown" {
                     instruction.values.push(Event::End(event));
+                    if unknown_depth > 0 {
+                        unknown_depth -= 1;
+                    } else {
+                        commands.extend(stack.pop_front().map(|command| command.set(instruction)));
+                        stack.clear();
+                        instruction = InstructionSet::new();
+                    }
                 } else {
-                    // println!("[ENDING] tag {tag} ({command}) / {last_command}");
+                    if command.as_ref() == "unknown" && !start_tag.is_empty() {
+                        command = Command::StartTag(Some(start_tag.to_string()));
+                    }
 
-                    commands.push(command.set(instruction));
-                    stack.clear();
-                    instruction = InstructionSet::new();
+                    if command::COLLECTION_COMMANDS.contains(&last_command) && command.as_ref() != last_command {
+                        instruction.values.push(Event::End(event));
+                    } else {
+                        // println!("[ENDING] tag {tag} ({command}) / {last_command}");
+
+                        if command::COLLECTION_COMMANDS.contains(&last_command) {
+                            instruction
+                                .check_well_formed()
+                                .map_err(|err| format!("<{tag_name}> content is not well-formed: {err}"))?;
+                        }
+                        // Keep the source's style of an empty command written with an end tag
+                        if command::EMPTY_COMMANDS.contains(&last_command) {
+                            instruction.explicit_end = true;
+                        }
+
+                        commands.push(command.set(instruction));
+                        stack.clear();
+                        instruction = InstructionSet::new();
+                    }
                 }
             }
 
             // exits the loop when reaching end of file
-            Ok(Event::Eof) => break,
+            Ok(Event::Eof) => {
+                if let Some(command) = stack.front() {
+                    return Err(format!("<{command}> is never closed").into());
+                }
 
-            // Something unexpected happened. Panic and exit.
-            Ok(event) => {
-                panic!("[UNKNOWN] event: {:?}", event.as_ref());
+                break;
             }
+
+            // The XML declaration, processing instructions and doctypes don't affect the commands
+            Ok(Event::Decl(_) | Event::PI(_) | Event::DocType(_)) => {}
         }
 
         buf.clear();
@@ -186,8 +369,7 @@ fn load_xml(path: &Path) -> eyre::Result<Vec<Command>> {
 }
 
 fn get_attribute(e: &quick_xml::events::BytesStart, attr: &str) -> Option<Vec<u8>> {
-    for attribute in e.attributes() {
-        let attribute = attribute.unwrap();
+    for attribute in e.attributes().flatten() {
         if str::from_utf8(attribute.key.as_ref()) == Ok(attr) {
             return Some(attribute.unescape_value().unwrap_or_default().as_bytes().to_owned());
         }
@@ -195,3 +377,205 @@ fn get_attribute(e: &quick_xml::events::BytesStart, attr: &str) -> Option<Vec<u8
 
     None
 }
+
+/// Collects the attributes of `e` not in `known`, unescaped and in their original order
+fn extra_attributes(e: &quick_xml::events::BytesStart, known: &[&str]) -> Result<Vec<RawAttribute>, quick_xml::Error> {
+    let mut extra = Vec::new();
+    for attribute in e.attributes() {
+        let attribute = attribute?;
+        if !known.iter().any(|key| key.as_bytes() == attribute.key.as_ref()) {
+            extra.push((
+                attribute.key.as_ref().to_vec(),
+                attribute.unescape_value()?.as_bytes().to_vec(),
+            ));
+        }
+    }
+
+    Ok(extra)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modlet::fixtures::xml_fixture;
+
+    fn write_to_string(xml: &ModletXML) -> String {
+        let mut writer = quick_xml::Writer::new(Vec::new());
+        xml.write(&mut writer).unwrap();
+
+        String::from_utf8(writer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_escaped_text_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("escaped_text.xml")).load().unwrap();
+
+        assert_eq!(
+            r#"<set xpath="/items/item[@name=&quot;gunPistol&quot;]/@value">Tom &amp; Jerry &lt;3</set>"#,
+            write_to_string(&xml)
+        );
+    }
+
+    #[test]
+    fn test_entity_reference_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("entity_reference.xml")).load().unwrap();
+
+        assert_eq!(
+            r#"<set xpath="/items/item[@name='gunPistol']/@Credits">Copyright &#169; Tom &amp; Jerry</set>"#,
+            write_to_string(&xml)
+        );
+
+        let err = ModletXML::new(xml_fixture("unknown_entity.xml")).load().unwrap_err();
+        assert!(err.to_string().contains("bogus"), "{err}");
+    }
+
+    #[test]
+    fn test_extra_attributes_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("extra_attributes.xml")).load().unwrap();
+
+        assert_eq!(
+            concat!(
+                r#"<append xpath="/items" tag="weapons" note="Tom &amp; Jerry"><item name="gunFork"/></append>"#,
+                r#"<setAttribute xpath="/items/item[@name='gunPistol']" name="Tags" source="fork">pistol</setAttribute>"#
+            ),
+            write_to_string(&xml)
+        );
+    }
+
+    #[rstest]
+    #[case::with_self_closing("remove_self_closing.xml", r#"<remove xpath="/items/item[@name='gunPistol']"/>"#)]
+    #[case::with_end_tag("remove_end_tag.xml", r#"<remove xpath="/items/item[@name='gunPistol']"></remove>"#)]
+    fn test_empty_command_style(#[case] name: &str, #[case] expected: &str) {
+        let xml = ModletXML::new(xml_fixture(name)).load().unwrap();
+
+        assert_eq!(expected, write_to_string(&xml));
+    }
+
+    #[test]
+    fn test_multiple_xpaths() {
+        let xml = ModletXML::new(xml_fixture("multiple_xpaths.xml")).load().unwrap();
+
+        assert_eq!(
+            1,
+            xml.commands.iter().filter(|command| command.kind() == "append").count()
+        );
+        assert_eq!(
+            concat!(
+                r#"<append xpath="/items/item[@name='gunPistol']"><property name="Stacknumber" value="2" /></append>"#,
+                r#"<append xpath="/items/item[@name='gunRifle']"><property name="Stacknumber" value="2" /></append>"#
+            ),
+            write_to_string(&xml)
+        );
+    }
+
+    #[test]
+    fn test_cdata_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("cdata.xml")).load().unwrap();
+
+        assert_eq!(
+            concat!(
+                r#"<set xpath="/items/item[@name='gunPistol']/@Description"><![CDATA[Tom & Jerry <3]]></set>"#,
+                r#"<append xpath="/items"><![CDATA[<item name="raw" />]]></append>"#
+            ),
+            write_to_string(&xml)
+        );
+    }
+
+    #[test]
+    fn test_append_text_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("append_text.xml")).load().unwrap();
+
+        assert_eq!(
+            r#"<append xpath="/items"><item name="gunText"><property name="Tags">weapon &amp; tool</property></item></append>"#,
+            write_to_string(&xml)
+        );
+    }
+
+    #[test]
+    fn test_comment_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("comment.xml")).load().unwrap();
+
+        assert_eq!(
+            r#"<!-- Tom & Jerry &amp; friends --><remove xpath="/items/item[@name='gunPistol']"></remove>"#,
+            write_to_string(&xml)
+        );
+    }
+
+    #[test]
+    fn test_unknown_command_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("unknown_command.xml")).load().unwrap();
+
+        let unknown = xml.commands.iter().filter(|command| command.as_ref() == "unknown");
+        assert_eq!(
+            vec!["apend", "Sett"],
+            unknown.map(Command::to_string).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            concat!(
+                r#"<apend xpath="/items"><item name="typo"><property name="Weight" value="3" /></item></apend>"#,
+                r#"<Sett xpath="/items/item[@name='gunPistol']/@name" />"#
+            ),
+            write_to_string(&xml)
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let err = ModletXML::new(xml_fixture("missing.xml")).load().unwrap_err();
+        assert!(matches!(err, ModletError::FileNotFound(_)));
+
+        let err = ModletXML::new(xml_fixture("mismatched.xml")).load().unwrap_err();
+        assert!(matches!(err, ModletError::ParseError { line: 3, .. }), "{err:?}");
+    }
+
+    #[rstest]
+    #[case::with_mismatched_child("unclosed_child.xml", 5, "Expecting </item> found </append>")]
+    #[case::with_truncated_file("truncated_append.xml", 5, "<append> is never closed")]
+    fn test_malformed_append(#[case] name: &str, #[case] expected_line: usize, #[case] expected_message: &str) {
+        match ModletXML::new(xml_fixture(name)).load().unwrap_err() {
+            ModletError::ParseError { line, message, .. } => {
+                assert_eq!(expected_line, line);
+                assert!(message.contains(expected_message), "{message}");
+            }
+            err => panic!("unexpected error: {err:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case::with_unclosed_root("unclosed_root.xml", 1, "<configs> is never closed")]
+    #[case::with_stray_close("stray_close.xml", 4, "found </configs>")]
+    fn test_tag_balance(#[case] name: &str, #[case] expected_line: usize, #[case] expected_message: &str) {
+        match ModletXML::new(xml_fixture(name)).load().unwrap_err() {
+            ModletError::ParseError { line, message, .. } => {
+                assert_eq!(expected_line, line);
+                assert!(message.contains(expected_message), "{message}");
+            }
+            err => panic!("unexpected error: {err:?}"),
+        }
+    }
+
+    #[rstest]
+    #[case::with_stray_text("stray_text.xml", r#"Unexpected text "stray text" outside of a command"#)]
+    #[case::with_stray_element("stray_element.xml", r#"Unexpected <item name="gunFork"/> outside of"#)]
+    #[case::with_empty_set(
+        "empty_set.xml",
+        r#"<set xpath="/items/item[@name='gunPistol']/@value"/> requires a value"#
+    )]
+    fn test_unexpected_content(#[case] name: &str, #[case] expected_message: &str) {
+        let err = ModletXML::new(xml_fixture(name)).load().unwrap_err();
+
+        assert!(matches!(err, ModletError::ParseError { .. }), "{err:?}");
+        assert!(err.to_string().contains(expected_message), "{err}");
+        assert!(err.to_string().contains(name), "{err}");
+    }
+
+    #[test]
+    fn test_csv_set_roundtrip() {
+        let xml = ModletXML::new(xml_fixture("csv_set.xml")).load().unwrap();
+
+        assert_eq!(
+            r#"<csv xpath="/items/item[@name='gunPistol']/property[@name='Tags']/@value" delim="," op="set">pistol,ranged</csv>"#,
+            write_to_string(&xml)
+        );
+    }
+}
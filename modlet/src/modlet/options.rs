@@ -0,0 +1,265 @@
+use glob::Pattern;
+use std::{fmt, io, path::Path, str::FromStr, sync::Arc};
+
+/// The conventional filename of a modlet's ModInfo
+pub const MODINFO_FILENAME: &str = "ModInfo.xml";
+
+/// Options controlling how a modlet is loaded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModletOptions {
+    /// The filename of the modlet's ModInfo, matched case-insensitively (default: `ModInfo.xml`)
+    pub modinfo_filename: Option<String>,
+    /// Load a modlet without a config directory as an empty modlet instead of returning an error
+    pub allow_missing_config: bool,
+    /// Skip XML files which fail to load instead of returning an error (they are reported by `validate`)
+    pub allow_invalid_xml: bool,
+    /// File extensions (without the leading `.`) which are never loaded, even if they would otherwise be included
+    pub exclude_extensions: Vec<String>,
+}
+
+impl ModletOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_modinfo_filename(mut self, filename: impl ToString) -> Self {
+        self.modinfo_filename = Some(filename.to_string());
+        self
+    }
+
+    pub fn with_allow_missing_config(mut self, allow: bool) -> Self {
+        self.allow_missing_config = allow;
+        self
+    }
+
+    pub fn with_allow_invalid_xml(mut self, allow: bool) -> Self {
+        self.allow_invalid_xml = allow;
+        self
+    }
+
+    pub fn with_exclude_extensions(mut self, extensions: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.exclude_extensions = extensions.into_iter().map(|ext| ext.to_string()).collect();
+        self
+    }
+
+    /// Returns true if files with the extension `ext` should be skipped (matched case-insensitively)
+    pub fn is_excluded_extension(&self, ext: &str) -> bool {
+        self.exclude_extensions
+            .iter()
+            .any(|excluded| excluded.trim_start_matches('.').eq_ignore_ascii_case(ext))
+    }
+
+    /// Returns the ModInfo filename to look for
+    pub fn modinfo_filename(&self) -> &str {
+        self.modinfo_filename.as_deref().unwrap_or(MODINFO_FILENAME)
+    }
+}
+
+/// Options controlling which optional validation checks are run
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationOptions {
+    /// Report commands whose xpath is relative (does not start with `/`) as errors
+    pub strict_xpath: bool,
+    /// Report tags which are not known modlet commands (e.g. a misspelled `<apend>`) as errors
+    pub strict_commands: bool,
+}
+
+impl ValidationOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_strict_xpath(mut self, strict: bool) -> Self {
+        self.strict_xpath = strict;
+        self
+    }
+
+    pub fn with_strict_commands(mut self, strict: bool) -> Self {
+        self.strict_commands = strict;
+        self
+    }
+}
+
+/// The default number of times a failed file operation is retried
+pub const DEFAULT_IO_RETRIES: u32 = 3;
+
+/// What to do with the header line of a localization file appended to an existing one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LocalizationHeader {
+    /// Drop the appended file's first line
+    #[default]
+    Skip,
+    /// Keep the appended file's header, as a `#` comment line
+    Comment,
+    /// Drop the appended file's first line only if it repeats the existing file's header
+    KeepFirstOnly,
+}
+
+impl FromStr for LocalizationHeader {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "skip" => Ok(Self::Skip),
+            "comment" => Ok(Self::Comment),
+            "keep-first-only" => Ok(Self::KeepFirstOnly),
+            _ => Err(format!(
+                "unknown header mode {mode} (expected skip, comment or keep-first-only)"
+            )),
+        }
+    }
+}
+
+/// The line ending used when appending to a localization file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\r\n`, which the game expects
+    #[default]
+    Crlf,
+    Lf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Lf => "\n",
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(ending: &str) -> Result<Self, Self::Err> {
+        match ending.to_ascii_lowercase().as_str() {
+            "crlf" => Ok(Self::Crlf),
+            "lf" => Ok(Self::Lf),
+            _ => Err(format!("unknown line ending {ending} (expected crlf or lf)")),
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineEnding::Crlf => write!(f, "crlf"),
+            LineEnding::Lf => write!(f, "lf"),
+        }
+    }
+}
+
+/// A callback notified of retried IO operations (see [`WriteOptions::with_on_retry`])
+#[derive(Clone)]
+pub struct RetryHook(Arc<RetryFn>);
+
+type RetryFn = dyn Fn(&Path, &io::Error, u32) + Send + Sync;
+
+impl RetryHook {
+    pub fn call(&self, path: &Path, error: &io::Error, attempt: u32) {
+        (self.0)(path, error, attempt)
+    }
+}
+
+impl fmt::Debug for RetryHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RetryHook")
+    }
+}
+
+impl PartialEq for RetryHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RetryHook {}
+
+/// Options controlling how a modlet's files are written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOptions {
+    /// How many times a transiently failing copy is retried before giving up (default: [`DEFAULT_IO_RETRIES`])
+    pub io_retries: u32,
+    /// Called before each retry of a failed copy, e.g. to log it (default: none)
+    pub on_retry: Option<RetryHook>,
+    /// Write comments from the source XML files (default: true)
+    pub include_comments: bool,
+    /// Mark where each modlet's content starts in a bundled XML file with an "Included from" comment (default: true)
+    pub include_sources: bool,
+    /// How the header of a localization file appended to an existing one is handled (default: skipped)
+    pub localization_header: LocalizationHeader,
+    /// The line ending of lines appended to an existing localization file (default: CRLF)
+    pub localization_line_ending: LineEnding,
+    /// Patterns of files (relative to the config directory) which are left out, e.g. from a `.dmtignore`
+    pub ignore: Vec<Pattern>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            io_retries: DEFAULT_IO_RETRIES,
+            on_retry: None,
+            include_comments: true,
+            include_sources: true,
+            localization_header: LocalizationHeader::default(),
+            localization_line_ending: LineEnding::default(),
+            ignore: Vec::new(),
+        }
+    }
+}
+
+impl WriteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_io_retries(mut self, retries: u32) -> Self {
+        self.io_retries = retries;
+        self
+    }
+
+    /// Calls `hook` with the file being copied, the error and the attempt number before each retry of a failed copy
+    pub fn with_on_retry(mut self, hook: impl Fn(&Path, &io::Error, u32) + Send + Sync + 'static) -> Self {
+        self.on_retry = Some(RetryHook(Arc::new(hook)));
+        self
+    }
+
+    pub fn with_include_comments(mut self, include: bool) -> Self {
+        self.include_comments = include;
+        self
+    }
+
+    pub fn with_include_sources(mut self, include: bool) -> Self {
+        self.include_sources = include;
+        self
+    }
+
+    pub fn with_localization_header(mut self, header: LocalizationHeader) -> Self {
+        self.localization_header = header;
+        self
+    }
+
+    pub fn with_localization_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.localization_line_ending = line_ending;
+        self
+    }
+
+    pub fn with_ignore(mut self, patterns: Vec<Pattern>) -> Self {
+        self.ignore = patterns;
+        self
+    }
+
+    /// Returns true if `file` (relative to the config directory) matches an ignore pattern
+    ///
+    /// As in a `.gitignore`, a pattern without a `/` (e.g. `*.debug.xml`) matches files of that name in any directory.
+    pub fn is_ignored(&self, file: &Path) -> bool {
+        self.ignore.iter().any(|pattern| {
+            pattern.matches_path(file)
+                || (!pattern.as_str().contains('/')
+                    && file
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| pattern.matches(name)))
+        })
+    }
+}
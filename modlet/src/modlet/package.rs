@@ -0,0 +1,156 @@
+use super::{file_map, Modlet, ModletError, WriteOptions};
+use glob::Pattern;
+use quick_xml::{
+    events::{BytesEnd, BytesStart, BytesText, Event},
+    Writer,
+};
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+/// The filename of the optional list of files to leave out of a packaged modlet, kept in the output modlet's root
+pub const IGNORE_FILENAME: &str = ".dmtignore";
+
+/// Packages `modlets` (in the order given) into the modlet at `output`, using the default [`WriteOptions`]
+///
+/// See [`package_with`].
+pub fn package(modlets: &[Modlet], output: &Path) -> Result<(), ModletError> {
+    package_with(modlets, output, &WriteOptions::default())
+}
+
+/// Packages `modlets` (in the order given) into the modlet at `output`
+///
+/// The output's `Config` directory is replaced: each XML file is bundled from every modlet which contains it, and
+/// the modlets' other files are copied (or merged, for localization files). Files ignored by `options` are left out,
+/// and the output's ModInfo is left untouched.
+pub fn package_with(modlets: &[Modlet], output: &Path, options: &WriteOptions) -> Result<(), ModletError> {
+    clear_config_dir(output)?;
+
+    for (file, modlets) in file_map(modlets)
+        .into_iter()
+        .filter(|(file, _)| !options.is_ignored(file))
+    {
+        bundle_xml(&file, &modlets, output, options)?;
+    }
+    for modlet in modlets {
+        modlet.write_files_with(output, options)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the glob patterns from the `.dmtignore` of the modlet at `output`, if it has one
+///
+/// Patterns are relative to the config directory, one per line; blank lines and `#` comments are skipped.
+pub fn read_ignore_file(output: &Path) -> Result<Vec<Pattern>, ModletError> {
+    let file = output.join(IGNORE_FILENAME);
+    if !file.is_file() {
+        return Ok(Vec::new());
+    }
+
+    fs::read_to_string(&file)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| Pattern::new(line.trim_start_matches('/')).map_err(ModletError::from))
+        .collect()
+}
+
+/// Removes the `Config` directory of the modlet at `output`, so no previously packaged files are left behind
+pub fn clear_config_dir(output: &Path) -> Result<(), ModletError> {
+    let config_dir = output.join("Config");
+
+    if config_dir.is_dir() {
+        fs::remove_dir_all(&config_dir)?;
+    } else if config_dir.exists() {
+        return Err(ModletError::InvalidModletConfig {
+            path: config_dir,
+            message: String::from("Config is not a directory"),
+        });
+    }
+
+    Ok(())
+}
+
+/// Writes the commands for `file` (relative to `Config/`) from each of `modlets`, in order, into a single `<bundle>`
+/// in the `Config` directory of the modlet at `output`
+pub fn bundle_xml(file: &Path, modlets: &[&Modlet], output: &Path, options: &WriteOptions) -> Result<(), ModletError> {
+    let config_file = output.join("Config").join(file);
+
+    if config_file.exists() {
+        fs::remove_file(&config_file)?;
+    } else if let Some(parent) = config_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let config_file = File::create(&config_file)?;
+    let mut writer = Writer::new_with_indent(&config_file, b' ', 4);
+
+    writer.write_event(Event::Start(BytesStart::new("bundle")))?;
+
+    for modlet in modlets {
+        // Inject a comment to indicate which modlet the xml came from
+        if options.include_sources {
+            writer.write_event(Event::Comment(BytesText::new(
+                format!(" Included from {} ", modlet.name()).as_str(),
+            )))?;
+        }
+
+        modlet.write_xmls_with(&mut writer, file, options)?;
+    }
+
+    writer.write_event(Event::End(BytesEnd::new("bundle")))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modlet::fixtures::fixture;
+
+    #[test]
+    fn test_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Bundle");
+        let modlets = [
+            Modlet::new(fixture("Localized")).unwrap(),
+            Modlet::new(fixture("CapitalConfig")).unwrap(),
+        ];
+
+        package(&modlets, &output).unwrap();
+
+        let blocks = fs::read_to_string(output.join("Config/blocks.xml")).unwrap();
+        assert!(blocks.starts_with("<bundle>"), "{blocks}");
+        assert!(
+            blocks.find("Included from Localized") < blocks.find("Included from CapitalConfig"),
+            "{blocks}"
+        );
+        assert!(output.join("Config/Localization.txt").is_file());
+        assert!(output.join("Config/quests/localization.txt").is_file());
+    }
+
+    #[test]
+    fn test_ignore_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("Source");
+        let output = dir.path().join("Bundle");
+        fs::create_dir_all(source.join("Config")).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        for file in ["items.xml", "items.debug.xml"] {
+            fs::write(
+                source.join("Config").join(file),
+                r#"<configs><set xpath="/items/item[@name='gunPistol']/@value">2</set></configs>"#,
+            )
+            .unwrap();
+        }
+        fs::write(output.join(IGNORE_FILENAME), "# Debugging aids\n*.debug.xml\n").unwrap();
+
+        let options = WriteOptions::new().with_ignore(read_ignore_file(&output).unwrap());
+        package_with(&[Modlet::new(&source).unwrap()], &output, &options).unwrap();
+
+        assert!(output.join("Config/items.xml").is_file());
+        assert!(!output.join("Config/items.debug.xml").exists());
+    }
+}
@@ -0,0 +1,448 @@
+use super::{Modlet, ModletError, ValidationOptions};
+use modinfo::ModinfoVersion;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// How serious a validation problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found while validating a modlet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn warning(message: impl ToString) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn error(message: impl ToString) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// The outcome of a single validation check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub problems: Vec<Problem>,
+}
+
+impl Check {
+    pub fn new(name: &'static str, problems: Vec<Problem>) -> Self {
+        Self { name, problems }
+    }
+
+    /// Returns true if the check found no problems at all
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Modlet {
+    /// Runs every default validation check against the modlet
+    pub fn checks(&self) -> Vec<Check> {
+        self.checks_with(&ValidationOptions::default())
+    }
+
+    /// Runs every default validation check, plus any optional checks enabled by `options`
+    pub fn checks_with(&self, options: &ValidationOptions) -> Vec<Check> {
+        let mut checks = vec![
+            Check::new("modinfo", self.modinfo_problems()),
+            Check::new("config directory", self.config_dir_problems()),
+            Check::new("xml files", self.xml_problems()),
+            Check::new("xpaths", self.xpath_problems()),
+            Check::new("self-referential inserts", self.self_referential_problems()),
+            Check::new("known commands", self.unknown_command_problems(options.strict_commands)),
+            Check::new("misplaced xml", self.misplaced_xmls()),
+            Check::new("localization line endings", self.line_ending_problems()),
+        ];
+
+        if options.strict_xpath {
+            checks.push(Check::new("absolute xpaths", self.relative_xpath_problems()));
+        }
+
+        checks
+    }
+
+    /// Validates the modlet, returning every problem found
+    pub fn validate(&self) -> Vec<Problem> {
+        self.checks().into_iter().flat_map(|check| check.problems).collect()
+    }
+
+    /// Applies safe automatic fixes for problems found by [`validate`](Modlet::validate)
+    ///
+    /// Fixes the config directory casing, a missing display name (defaulted from the name), and LF line endings in
+    /// localization files. Returns a description of every change made.
+    pub fn fix(&mut self) -> Result<Vec<String>, ModletError> {
+        let mut fixes = Vec::new();
+
+        let config_root = self.config_root();
+        let config_dir = self.path.join("Config");
+        if config_root.is_dir() && config_root.file_name().unwrap_or_default() != "Config" {
+            fs::rename(&config_root, &config_dir)?;
+            self.rebase_config(&config_root, &config_dir);
+            fixes.push(format!(
+                "Renamed {} to Config",
+                config_root.file_name().unwrap_or_default().to_string_lossy()
+            ));
+        }
+
+        if self.is_missing_display_name() {
+            let name = match self.modinfo.get_value_for("name") {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => self.name().to_string(),
+            };
+
+            self.modinfo.set_value_for("display_name", &name);
+            self.modinfo.write(self.modinfo_file.as_deref())?;
+            fixes.push(format!("Set display_name to {name}"));
+        }
+
+        for file in self.lf_localization_files() {
+            let content = fs::read_to_string(&file)?;
+            let mut normalized = content.lines().collect::<Vec<_>>().join("\r\n");
+            if content.ends_with('\n') {
+                normalized.push_str("\r\n");
+            }
+
+            fs::write(&file, normalized)?;
+            fixes.push(format!(
+                "Converted {} to CRLF line endings",
+                file.strip_prefix(&self.path).unwrap_or(&file).display()
+            ));
+        }
+
+        Ok(fixes)
+    }
+
+    /// Returns true if validation finds no problems at all
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_empty()
+    }
+
+    /// Checks that a ModInfo.xml exists and has its required fields
+    fn modinfo_problems(&self) -> Vec<Problem> {
+        if self.modinfo_file.is_none() {
+            return vec![Problem::error("ModInfo.xml does not exist")];
+        }
+
+        let mut problems = ["name", "description", "author"]
+            .into_iter()
+            .filter(|field| self.modinfo.get_value_for(field).map_or(true, |value| value.is_empty()))
+            .map(|field| Problem::error(format!("ModInfo.xml is missing a {field}")))
+            .collect::<Vec<_>>();
+
+        if self.is_missing_display_name() {
+            problems.push(Problem::warning("ModInfo.xml is missing a display_name"));
+        }
+
+        problems
+    }
+
+    /// Checks that the config directory exists, is conventionally named and contains XML files
+    fn config_dir_problems(&self) -> Vec<Problem> {
+        let config_root = self.config_root();
+        if !config_root.is_dir() {
+            return vec![Problem::error("Config directory does not exist")];
+        }
+
+        let mut problems = Vec::new();
+        if config_root.file_name().unwrap_or_default() != "Config" {
+            problems.push(Problem::warning(format!(
+                "Config directory should be named Config (found {})",
+                config_root.file_name().unwrap_or_default().to_string_lossy()
+            )));
+        }
+        if self.xmls.is_empty() && self.load_errors.is_empty() {
+            problems.push(Problem::warning("Config directory contains no XML files"));
+        }
+
+        problems
+    }
+
+    /// Reports the XML files which could not be loaded (see [`ModletOptions::allow_invalid_xml`])
+    ///
+    /// [`ModletOptions::allow_invalid_xml`]: super::ModletOptions::allow_invalid_xml
+    fn xml_problems(&self) -> Vec<Problem> {
+        self.load_errors.iter().map(Problem::error).collect()
+    }
+
+    /// Checks that every command which operates on an xpath has one
+    fn xpath_problems(&self) -> Vec<Problem> {
+        self.xmls
+            .iter()
+            .flat_map(|xml| {
+                xml.commands
+                    .iter()
+                    .filter(|command| command.instruction_set().is_some_and(|is| is.xpath.is_empty()))
+                    .map(move |command| Problem::error(format!("{}: {command} has no xpath", xml.filename().display())))
+            })
+            .collect()
+    }
+
+    /// Reports commands whose xpath is relative; these are usually mistakes, but valid in some advanced usage
+    fn relative_xpath_problems(&self) -> Vec<Problem> {
+        self.xmls
+            .iter()
+            .flat_map(|xml| {
+                xml.commands
+                    .iter()
+                    .filter_map(|command| command.instruction_set().map(|is| (command, is)))
+                    .flat_map(|(command, is)| is.xpaths().map(move |xpath| (command, xpath)))
+                    .filter(|(_, xpath)| !xpath.is_empty() && !xpath.starts_with(b"/"))
+                    .map(move |(command, xpath)| {
+                        Problem::error(format!(
+                            "{}: {command} has a relative xpath ({})",
+                            xml.filename().display(),
+                            String::from_utf8_lossy(xpath)
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    /// Reports unrecognised (e.g. misspelled) commands, which are packaged verbatim; errors in strict mode
+    fn unknown_command_problems(&self, strict: bool) -> Vec<Problem> {
+        let problem: fn(String) -> Problem = if strict { Problem::error } else { Problem::warning };
+
+        self.xmls
+            .iter()
+            .flat_map(|xml| {
+                xml.commands
+                    .iter()
+                    .filter(|command| command.as_ref() == "unknown")
+                    .map(move |command| problem(format!("{}: unknown command <{command}>", xml.filename().display())))
+            })
+            .collect()
+    }
+
+    /// Warns about inserts whose content looks like it matches their own xpath target
+    fn self_referential_problems(&self) -> Vec<Problem> {
+        self.xmls
+            .iter()
+            .flat_map(|xml| {
+                xml.commands
+                    .iter()
+                    .filter(|command| command.is_self_referential())
+                    .map(move |command| {
+                        Problem::warning(format!(
+                            "{}: {command} adds content matching its own xpath target",
+                            xml.filename().display()
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    fn line_ending_problems(&self) -> Vec<Problem> {
+        self.lf_localization_files()
+            .into_iter()
+            .map(|file| {
+                Problem::warning(format!(
+                    "{} uses LF line endings instead of CRLF",
+                    file.strip_prefix(&self.path).unwrap_or(&file).display()
+                ))
+            })
+            .collect()
+    }
+
+    /// V2 ModInfo files should have a display name
+    fn is_missing_display_name(&self) -> bool {
+        self.modinfo_file.is_some()
+            && self.modinfo.get_modinfo_version() == ModinfoVersion::V2
+            && self
+                .modinfo
+                .get_value_for("display_name")
+                .map_or(true, |value| value.is_empty())
+    }
+
+    /// Finds localization files containing LF line endings (the game expects CRLF)
+    fn lf_localization_files(&self) -> Vec<PathBuf> {
+        self.localization_files()
+            .into_iter()
+            .filter(|file| {
+                let content = fs::read(file).unwrap_or_default();
+                content.first() == Some(&b'\n') || content.windows(2).any(|pair| pair[1] == b'\n' && pair[0] != b'\r')
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Points every loaded file under `from` at `to` instead, after the config directory has been renamed
+    fn rebase_config(&mut self, from: &Path, to: &Path) {
+        let rebase = |file: &Path| to.join(file.strip_prefix(from).unwrap_or(file));
+
+        for xml in &mut self.xmls {
+            xml.path = rebase(&xml.path);
+        }
+        for file in self.files.iter_mut().flatten() {
+            *file = rebase(file);
+        }
+    }
+
+    /// Finds config XML files which live in the modlet root instead of under `Config/`
+    ///
+    /// These are silently ignored when the modlet is loaded, so we treat any root XML (other than the ModInfo)
+    /// containing an `xpath` attribute as a likely mistake.
+    fn misplaced_xmls(&self) -> Vec<Problem> {
+        fs::read_dir(&self.path)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|file| {
+                file.is_file()
+                    && file.extension().unwrap_or_default().eq_ignore_ascii_case("xml")
+                    && !file.file_name().unwrap_or_default().eq_ignore_ascii_case("modinfo.xml")
+            })
+            .filter(|file| fs::read_to_string(file).unwrap_or_default().contains("xpath="))
+            .map(|file| {
+                Problem::warning(format!(
+                    "{} looks like a config file but is not under Config/ and will be ignored",
+                    file.file_name().unwrap_or_default().to_string_lossy()
+                ))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modlet::{
+        fixtures::{fixture, load_fixture},
+        ModletOptions,
+    };
+
+    #[rstest]
+    #[case::with_valid_modlet("CapitalConfig", true)]
+    #[case::with_misplaced_xml("MisplacedXml", false)]
+    #[case::with_missing_modinfo("NoModinfo", false)]
+    fn test_is_valid(#[case] name: &str, #[case] expected: bool) {
+        assert_eq!(expected, load_fixture(name).is_valid());
+    }
+
+    #[test]
+    fn test_fix_display_name() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("Config")).unwrap();
+        fs::write(
+            dir.path().join("ModInfo.xml"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<xml>
+  <Name value="FixMe" />
+  <Description value="A modlet without a display name" />
+  <Author value="DonovanMods" />
+  <Version value="1.0.0" />
+</xml>"#,
+        )
+        .unwrap();
+
+        let mut modlet = Modlet::new(dir.path()).unwrap();
+        assert!(modlet
+            .validate()
+            .contains(&Problem::warning("ModInfo.xml is missing a display_name")));
+
+        assert_eq!(vec!["Set display_name to FixMe"], modlet.fix().unwrap());
+        assert_eq!(
+            Some(String::from("FixMe")),
+            Modlet::new(dir.path())
+                .unwrap()
+                .modinfo
+                .get_value_for("display_name")
+                .map(|name| name.to_string())
+        );
+    }
+
+    #[test]
+    fn test_misplaced_xml() {
+        let problems = load_fixture("MisplacedXml").validate();
+
+        assert_eq!(1, problems.len());
+        assert_eq!(Severity::Warning, problems[0].severity);
+        assert!(problems[0].message.starts_with("items.xml"));
+    }
+
+    #[test]
+    fn test_strict_xpath() {
+        let modlet = load_fixture("RelativeXpath");
+        assert!(modlet.checks().iter().all(Check::passed));
+
+        let checks = modlet.checks_with(&ValidationOptions::new().with_strict_xpath(true));
+        let failed = checks.iter().filter(|check| !check.passed()).collect::<Vec<_>>();
+
+        assert_eq!(1, failed.len());
+        assert_eq!("absolute xpaths", failed[0].name);
+        assert_eq!(
+            vec![Problem::error(
+                "items.xml: set has a relative xpath (item[@name='gunPistol']/property[@name='Weight']/@value)"
+            )],
+            failed[0].problems
+        );
+    }
+
+    #[test]
+    fn test_strict_commands() {
+        let modlet = load_fixture("Misspelled");
+        assert_eq!(
+            vec![Problem::warning("items.xml: unknown command <apend>")],
+            modlet.validate()
+        );
+
+        let checks = modlet.checks_with(&ValidationOptions::new().with_strict_commands(true));
+        let failed = checks.iter().filter(|check| !check.passed()).collect::<Vec<_>>();
+
+        assert_eq!(1, failed.len());
+        assert_eq!("known commands", failed[0].name);
+        assert_eq!(
+            vec![Problem::error("items.xml: unknown command <apend>")],
+            failed[0].problems
+        );
+    }
+
+    #[test]
+    fn test_checks() {
+        let path = fixture("Broken");
+        let options = ModletOptions::new().with_allow_invalid_xml(true);
+        let checks = Modlet::with_options(path, &options).unwrap().checks();
+        let failed = checks
+            .iter()
+            .filter(|check| !check.passed())
+            .map(|check| check.name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["xml files", "xpaths"], failed);
+        assert!(checks[2].problems[0].message.contains("items.xml (line 5)"));
+        assert_eq!(Problem::error("blocks.xml: set has no xpath"), checks[3].problems[0]);
+    }
+}
@@ -3,3 +3,6 @@ pub use cli::SETTINGS;
 
 pub mod commands;
 pub mod helpers;
+
+#[cfg(test)]
+mod fixtures;
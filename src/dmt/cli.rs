@@ -1,10 +1,13 @@
 use super::commands;
-use crate::dmt::helpers::verify_modlet_paths;
+use crate::dmt::helpers::{
+    changed_modlets, discover_modlets, exclude_modlets, expand_paths, git_changed_files, verify_modlet_path,
+};
 use crate::CommandResult;
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use lazy_static::lazy_static;
+use modlet::modlet::{LineEnding, LocalizationHeader, ModletOptions};
 use serde::{Deserialize, Serialize};
-use std::{fmt, path::PathBuf, sync::RwLock};
+use std::{fmt, fs, path::PathBuf, sync::RwLock};
 use thiserror::Error;
 
 #[derive(Debug, Parser)]
@@ -18,9 +21,25 @@ pub struct Cli {
     #[arg(short, long, global = true, action = clap::ArgAction::Count)]
     verbose: u8,
 
+    /// Quiet mode: suppress progress and success messages (errors are still reported)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
     #[arg(short, long, global = true, value_name = "PATH")]
     game_directory: Option<PathBuf>,
 
+    /// The number of threads used for parallel work (default: one per CPU)
+    #[arg(short, long, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Skip files with these extensions when loading modlets (may be repeated, or comma separated)
+    #[arg(long, global = true, value_name = "EXT", value_delimiter = ',')]
+    exclude_ext: Vec<String>,
+
+    /// Output format for command results
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -36,6 +55,25 @@ pub enum Commands {
         #[command(flatten)]
         /// The version to set
         vers: Vers,
+
+        /// Show the version changes without writing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Summarize the changes between two versions of a modlet
+    #[command(arg_required_else_help = true)]
+    Changelog {
+        /// The older version of the modlet
+        old: PathBuf,
+
+        /// The newer version of the modlet
+        new: PathBuf,
+    },
+    /// Print a shell completion script to stdout
+    #[command(arg_required_else_help = true)]
+    Completions {
+        /// The shell to generate completions for
+        shell: clap_complete::Shell,
     },
     /// Convert a ModInfo.xml from V1 to V2 (or vice versa)
     #[command(arg_required_else_help = true)]
@@ -46,6 +84,25 @@ pub enum Commands {
         /// [Optionally] the ModInfo version to convert to (default: V2)
         #[command(flatten)]
         requested_version: Option<RequestedVersion>,
+
+        /// Copy each ModInfo.xml to ModInfo.xml.bak (or .bak.1, .bak.2, ...) before overwriting it
+        #[arg(long)]
+        backup: bool,
+    },
+    /// Compare the commands of two versions of a modlet
+    #[command(arg_required_else_help = true)]
+    Diff {
+        /// The first (e.g. upstream) modlet
+        a: PathBuf,
+
+        /// The second (e.g. forked) modlet
+        b: PathBuf,
+    },
+    /// Print the parsed commands of a single modlet XML file (for reporting parser bugs)
+    #[command(hide = true, arg_required_else_help = true)]
+    DumpAst {
+        /// The modlet XML file to parse
+        file: PathBuf,
     },
     /// Initialize a new modlet
     #[command(arg_required_else_help = true)]
@@ -56,19 +113,114 @@ pub enum Commands {
         /// [Optionally] the ModInfo version to use (default: V2)
         #[command(flatten)]
         requested_version: Option<RequestedVersion>,
+
+        /// Also write an example Config/blocks.xml to start from
+        #[arg(long)]
+        with_example: bool,
     },
     // Future: We'll process instructions in special `dmt` xml sections to create
     // larger modlets -- ala lessgrind.
     /// Package Modlet(s)
     #[command(arg_required_else_help = true)]
     Package {
-        /// The modlet to package into
+        /// The modlet to package into (or a .zip archive to create)
         #[arg(short, long, value_name = "MODLET")]
         output: PathBuf,
 
         /// The modlet path(s) to operate on
         #[arg(value_name = "MODLET_PATHS", required = true)]
         modlets: Vec<PathBuf>,
+
+        /// Exclude the named modlet(s) from packaging (may be repeated)
+        #[arg(long, value_name = "NAME")]
+        exclude_modlet: Vec<String>,
+
+        /// Search up to N directory levels below each path for modlets (default: 0, or unlimited with --flatten)
+        #[arg(long, value_name = "N")]
+        max_depth: Option<usize>,
+
+        /// Also search inside modlets, packaging any nested modlets as independent modlets
+        #[arg(long)]
+        flatten: bool,
+
+        /// The number of threads used to write packaged files (default: --jobs)
+        #[arg(long, value_name = "N")]
+        parallel_files: Option<usize>,
+
+        /// The ModInfo.xml version to use for the output modlet (1 or 2)
+        #[arg(long, value_name = "VERSION", value_parser = clap::value_parser!(u8).range(1..=2))]
+        output_modinfo_version: Option<u8>,
+
+        /// How many times a failed file copy is retried before giving up
+        #[arg(long, value_name = "N", default_value_t = modlet::modlet::DEFAULT_IO_RETRIES)]
+        io_retries: u32,
+
+        /// Which comments to keep in the packaged XML files
+        #[arg(long, value_enum, value_name = "MODE", default_value_t)]
+        keep_comments: KeepComments,
+
+        /// Only regenerate the output modlet's manifest, without rewriting any other files
+        #[arg(long)]
+        manifest_only: bool,
+
+        /// Also compress the output modlet into <OUTPUT>.zip
+        #[arg(long)]
+        zip: bool,
+
+        /// Only package modlets with files changed (according to git) since --since-commit
+        #[arg(long)]
+        changed_only: bool,
+
+        /// How to handle the header of each appended localization file: skip, comment or keep-first-only
+        #[arg(long, value_name = "MODE", default_value = "skip")]
+        append_localization_header: LocalizationHeader,
+
+        /// The line ending (crlf or lf) of lines appended to a localization file
+        #[arg(long, value_name = "ENDING", default_value_t)]
+        localization_line_ending: LineEnding,
+
+        /// Base the output modlet's ModInfo.xml on this modlet's (keeping the output modlet's name)
+        #[arg(long, value_name = "MODLET")]
+        seed_from: Option<PathBuf>,
+
+        /// The git commit (or other ref) that --changed-only compares against
+        #[arg(long, value_name = "REF", default_value = "HEAD", requires = "changed_only")]
+        since_commit: String,
+
+        /// Replace the output modlet's Config directory even if it has files not written by a previous package run
+        #[arg(long)]
+        force: bool,
+    },
+    /// Validate Modlet(s)
+    #[command(arg_required_else_help = true)]
+    Validate {
+        /// The modlet path(s) to operate on
+        #[arg(value_name = "MODLET_PATHS", required = true)]
+        modlets: Vec<PathBuf>,
+
+        /// Automatically fix problems where it is safe to do so
+        #[arg(long)]
+        fix: bool,
+
+        /// Also write a JSON report of all modlets and their problems to this file
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+
+        /// Validate modlets in parallel; with --parallel=false they are validated one at a time, in order
+        #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+        parallel: bool,
+
+        /// Report any xpath which is not absolute (does not start with /) as an error
+        #[arg(long)]
+        strict_xpath: bool,
+
+        /// Report any tag which is not a known modlet command (e.g. a misspelled <apend>) as an error
+        #[arg(long)]
+        strict: bool,
+
+        /// Only print how many modlets passed and failed (still exiting with an error if any failed)
+        #[arg(long)]
+        count_only: bool,
     },
 }
 
@@ -76,13 +228,38 @@ impl fmt::Display for Commands {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Commands::Bump { .. } => write!(f, "Bump"),
+            Commands::Changelog { .. } => write!(f, "Changelog"),
+            Commands::Completions { .. } => write!(f, "Completions"),
             Commands::Convert { .. } => write!(f, "Convert"),
+            Commands::Diff { .. } => write!(f, "Diff"),
+            Commands::DumpAst { .. } => write!(f, "DumpAst"),
             Commands::Init { .. } => write!(f, "Init"),
             Commands::Package { .. } => write!(f, "Package"),
+            Commands::Validate { .. } => write!(f, "Validate"),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable messages
+    #[default]
+    Text,
+    /// Machine readable JSON
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeepComments {
+    /// Strip all comments
+    None,
+    /// Keep only the "Included from" markers between modlets
+    Section,
+    /// Keep source comments as well as the "Included from" markers
+    #[default]
+    All,
+}
+
 #[derive(Args, Debug)]
 #[group(required = true, multiple = true)]
 pub struct Vers {
@@ -101,6 +278,66 @@ pub struct Vers {
     /// auto inc patch
     #[arg(long)]
     patch: bool,
+
+    /// auto inc pre-release counter (e.g. alpha.1 -> alpha.2)
+    #[arg(long)]
+    pre_release_bump: bool,
+
+    /// set the pre-release label (e.g. beta), after any other bumps
+    #[arg(long, value_name = "LABEL", conflicts_with = "clear_pre")]
+    pre: Option<String>,
+
+    /// set the build metadata (e.g. 20240101), after any other bumps
+    #[arg(long, value_name = "LABEL", conflicts_with = "clear_build")]
+    build: Option<String>,
+
+    /// strip the pre-release label
+    #[arg(long)]
+    clear_pre: bool,
+
+    /// strip the build metadata
+    #[arg(long)]
+    clear_build: bool,
+}
+
+impl Vers {
+    /// Returns the bump options in the order they are applied: numeric bumps first, then labels
+    pub fn bump_options(&self) -> Vec<commands::bump::BumpOptions> {
+        use commands::bump::BumpOptions;
+
+        let mut opts = Vec::new();
+        if let Some(ver) = &self.ver {
+            opts.push(BumpOptions::Set(ver.clone()));
+        } else {
+            if self.major {
+                opts.push(BumpOptions::Major);
+            }
+            if self.minor {
+                opts.push(BumpOptions::Minor);
+            }
+            if self.patch {
+                opts.push(BumpOptions::Patch);
+            }
+            if self.pre_release_bump {
+                opts.push(BumpOptions::PreReleaseBump);
+            }
+        }
+
+        if self.clear_pre {
+            opts.push(BumpOptions::Pre(String::new()));
+        }
+        if self.clear_build {
+            opts.push(BumpOptions::Build(String::new()));
+        }
+        if let Some(pre) = &self.pre {
+            opts.push(BumpOptions::Pre(pre.clone()));
+        }
+        if let Some(build) = &self.build {
+            opts.push(BumpOptions::Build(build.clone()));
+        }
+
+        opts
+    }
 }
 
 #[derive(Args, Debug)]
@@ -114,11 +351,54 @@ pub struct RequestedVersion {
     pub v2: bool,
 }
 
+impl From<u8> for RequestedVersion {
+    /// Converts a numeric ModInfo.xml version (e.g. `1`) into a requested version
+    fn from(version: u8) -> Self {
+        Self {
+            v1: version == 1,
+            v2: version == 2,
+        }
+    }
+}
+
 #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
 pub struct Config {
-    #[serde(default)]
     pub game_directory: Option<PathBuf>,
+    /// The filename of a modlet's ModInfo, matched case-insensitively (default: `ModInfo.xml`)
+    pub modinfo_filename: Option<String>,
+    /// The number of threads used for parallel work (default: one per CPU)
+    pub jobs: Option<usize>,
+    /// File extensions which are never loaded from a modlet (e.g. `bak`)
+    pub exclude_extensions: Vec<String>,
     pub verbosity: u8,
+    /// Suppress progress and success messages
+    pub quiet: bool,
+}
+
+impl Config {
+    /// Loads the config from `file`, or from the default config file if it exists
+    pub fn load(file: Option<&PathBuf>) -> eyre::Result<Self> {
+        let default_file = dirs::config_dir().map(|dir| dir.join("7dmt").join("config.yaml"));
+        let file = match file {
+            Some(file) => file.to_owned(),
+            None => match default_file {
+                Some(file) if file.exists() => file,
+                _ => return Ok(Self::default()),
+            },
+        };
+
+        Ok(serde_yaml::from_str(&fs::read_to_string(file)?)?)
+    }
+
+    /// Returns the options used when loading modlets
+    pub fn modlet_options(&self) -> ModletOptions {
+        ModletOptions {
+            modinfo_filename: self.modinfo_filename.clone(),
+            exclude_extensions: self.exclude_extensions.clone(),
+            ..Default::default()
+        }
+    }
 }
 
 lazy_static! {
@@ -133,57 +413,108 @@ pub enum CliError {
     // NoGameDirectory,
     #[error("No modlet path specified")]
     NoModletPath,
+    #[error("Modlet {0} failed validation")]
+    ValidationFailed(String),
+    #[error("{0} modlet(s) failed validation")]
+    ModletsFailedValidation(usize),
+    #[error("{0} modlet(s) failed to package")]
+    PackageFailed(usize),
+    #[error("Could not load modlet {0}: {1}")]
+    LoadFailed(String, String),
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
+/// Generates the completion script for `shell`
+fn completions(shell: clap_complete::Shell) -> eyre::Result<String> {
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), env!("CARGO_BIN_NAME"), &mut script);
+
+    Ok(String::from_utf8(script)?)
+}
+
 pub fn run() -> eyre::Result<CommandResult> {
     let cli = Cli::parse();
-    let mut result = CommandResult::default();
 
-    SETTINGS.write().unwrap().game_directory = cli.game_directory;
-    SETTINGS.write().unwrap().verbosity = cli.verbose;
+    let mut config = Config::load(cli.config.as_ref())?;
+    if cli.game_directory.is_some() {
+        config.game_directory = cli.game_directory.clone();
+    }
+    if cli.jobs.is_some() {
+        config.jobs = cli.jobs;
+    }
+    config.exclude_extensions.extend(cli.exclude_ext.iter().cloned());
+    config.verbosity = cli.verbose;
+    config.quiet = cli.quiet;
+    *SETTINGS.write().unwrap() = config;
+
+    execute(&cli)
+}
+
+/// Runs the parsed command, using the already loaded `SETTINGS`
+fn execute(cli: &Cli) -> eyre::Result<CommandResult> {
+    let mut result = CommandResult::default();
 
     match &cli.command {
-        Commands::Bump { paths, vers } => {
+        Commands::Bump { paths, vers, dry_run } => {
             if paths.is_empty() {
                 result.errors.push(CliError::NoModletPath);
             } else {
                 let mut opts: Vec<commands::bump::BumpOptions> = Vec::new();
 
                 opts.push(commands::bump::BumpOptions::Verbosity(cli.verbose));
-
-                if let Some(ver) = &vers.ver {
-                    opts.push(commands::bump::BumpOptions::Set(ver.clone()));
-                } else {
-                    if vers.major {
-                        opts.push(commands::bump::BumpOptions::Major);
-                    }
-                    if vers.minor {
-                        opts.push(commands::bump::BumpOptions::Minor);
-                    }
-                    if vers.patch {
-                        opts.push(commands::bump::BumpOptions::Patch);
-                    }
+                if *dry_run {
+                    opts.push(commands::bump::BumpOptions::DryRun);
                 }
 
-                for path in paths {
+                opts.extend(vers.bump_options());
+
+                let mut reports = Vec::new();
+                for path in expand_paths(paths)? {
                     match commands::bump::run(path.clone(), opts.clone()) {
-                        Ok(msg) => result.messages.push(msg),
+                        Ok(report) => {
+                            // A dry run is only useful if it shows what would change
+                            if *dry_run && cli.format != OutputFormat::Json {
+                                result.output.push(report.to_string());
+                            } else {
+                                result.messages.push(report.to_string());
+                            }
+                            reports.push(report);
+                        }
                         Err(err) => result.errors.push(CliError::InvalidArg(err)),
                     }
                 }
+
+                if cli.format == OutputFormat::Json {
+                    result.output.push(serde_json::to_string_pretty(&reports)?);
+                }
+            }
+        }
+        Commands::Changelog { old, new } => match (verify_modlet_path(old), verify_modlet_path(new)) {
+            (Some(old), Some(new)) => {
+                let changelog = commands::changelog::run(&old, &new)?;
+
+                if cli.format == OutputFormat::Json {
+                    result.output.push(serde_json::to_string_pretty(&changelog)?);
+                } else {
+                    result.output.push(changelog.to_string());
+                }
             }
+            _ => result.errors.push(CliError::NoModletPath),
+        },
+        Commands::Completions { shell } => {
+            result.output.push(completions(*shell)?);
         }
         Commands::Convert {
             paths,
             requested_version,
+            backup,
         } => {
             if paths.is_empty() {
                 result.errors.push(CliError::NoModletPath);
             } else {
-                for path in paths {
-                    match commands::convert::run(path, requested_version.as_ref()) {
+                for path in expand_paths(paths)? {
+                    match commands::convert::run(&path, requested_version.as_ref(), *backup) {
                         Ok(_) => result
                             .messages
                             .push(format!("Successfully converted {}", path.display())),
@@ -192,31 +523,141 @@ pub fn run() -> eyre::Result<CommandResult> {
                 }
             }
         }
+        Commands::Diff { a, b } => match (verify_modlet_path(a), verify_modlet_path(b)) {
+            (Some(a), Some(b)) => {
+                let diff = commands::diff::run(&a, &b)?;
+
+                if cli.format == OutputFormat::Json {
+                    result.output.push(serde_json::to_string_pretty(&diff)?);
+                } else {
+                    result.output.push(diff.to_string());
+                }
+            }
+            _ => result.errors.push(CliError::NoModletPath),
+        },
+        Commands::DumpAst { file } => {
+            result.output.push(commands::dump_ast::run(file)?);
+        }
         Commands::Init {
             name,
             requested_version,
+            with_example,
         } => {
             if name.is_empty() {
                 result
                     .errors
                     .push(CliError::Unknown(String::from("No modlet name specified")));
             } else {
-                match commands::init::run(name.clone(), requested_version.as_ref()) {
+                match commands::init::run(name.clone(), requested_version.as_ref(), *with_example) {
                     Ok(true) => result.messages.push(format!("Created Modlet {}", name)),
                     Ok(false) => result.messages.push("Cancelled".to_owned()),
                     Err(err) => result.errors.push(CliError::Unknown(err.to_string())),
                 }
             }
         }
-        Commands::Package { modlets, output } => {
+        Commands::Package {
+            modlets,
+            output,
+            exclude_modlet,
+            max_depth,
+            flatten,
+            parallel_files,
+            output_modinfo_version,
+            io_retries,
+            keep_comments,
+            manifest_only,
+            zip,
+            changed_only,
+            since_commit,
+            append_localization_header,
+            localization_line_ending,
+            seed_from,
+            force,
+        } => {
             // if SETTINGS.read().unwrap().game_directory.is_none() {
             //     result.errors.push(CliError::NoGameDirectory);
             // }
             if modlets.is_empty() {
                 result.errors.push(CliError::NoModletPath);
             } else {
-                let verified_paths = verify_modlet_paths(modlets)?;
-                commands::package::run(&verified_paths, output)?
+                let max_depth = max_depth.unwrap_or(if *flatten { usize::MAX } else { 0 });
+                let mut verified_paths =
+                    exclude_modlets(discover_modlets(modlets, max_depth, *flatten)?, exclude_modlet);
+                if *changed_only {
+                    verified_paths = changed_modlets(verified_paths, |dir| git_changed_files(dir, since_commit))?;
+                }
+
+                if verified_paths.is_empty() && *changed_only {
+                    result
+                        .messages
+                        .push(format!("No modlets have changed since {since_commit}"));
+                } else if verified_paths.is_empty() {
+                    result.errors.push(CliError::NoModletPath);
+                } else {
+                    let options = commands::package::PackageOptions {
+                        jobs: SETTINGS.read().unwrap().jobs,
+                        parallel_files: *parallel_files,
+                        output_modinfo_version: *output_modinfo_version,
+                        io_retries: *io_retries,
+                        keep_comments: *keep_comments,
+                        manifest_only: *manifest_only,
+                        zip: *zip,
+                        localization_header: *append_localization_header,
+                        localization_line_ending: *localization_line_ending,
+                        seed_from: seed_from.clone(),
+                        force: *force,
+                    };
+                    match commands::package::run(&verified_paths, output, &options) {
+                        Ok(()) => (),
+                        Err(err) => match err.downcast::<commands::package::LoadFailures>() {
+                            Ok(commands::package::LoadFailures(failures)) => {
+                                result.errors.push(CliError::PackageFailed(failures.len()));
+                                for (path, err) in failures {
+                                    result
+                                        .errors
+                                        .push(CliError::LoadFailed(path.display().to_string(), err));
+                                }
+                            }
+                            Err(err) => return Err(err),
+                        },
+                    }
+                }
+            }
+        }
+        Commands::Validate {
+            modlets,
+            fix,
+            report,
+            parallel,
+            strict_xpath,
+            strict,
+            count_only,
+        } => {
+            if modlets.is_empty() {
+                result.errors.push(CliError::NoModletPath);
+            } else {
+                // Every named modlet is validated, so a missing or unparsable ModInfo is reported as a failure
+                let paths = expand_paths(modlets)?;
+                let options = commands::validate::ValidateOptions {
+                    fix: *fix,
+                    report: report.clone(),
+                    parallel: *parallel,
+                    strict_xpath: *strict_xpath,
+                    strict: *strict,
+                    count_only: *count_only,
+                };
+                let reports = commands::validate::run(&paths, &options)?;
+                let failed = commands::validate::failed_count(&reports);
+
+                if *count_only {
+                    if failed > 0 {
+                        result.errors.push(CliError::ModletsFailedValidation(failed));
+                    }
+                } else {
+                    for report in reports.iter().filter(|report| report.has_errors()) {
+                        result.errors.push(CliError::ValidationFailed(report.name()));
+                    }
+                }
             }
         }
     };
@@ -230,4 +671,103 @@ mod tests {
         use clap::CommandFactory;
         super::Cli::command().debug_assert()
     }
+
+    #[test]
+    fn quiet_conflicts_with_verbose() {
+        use super::{Cli, Parser};
+
+        let err = Cli::try_parse_from(["7dmt", "--quiet", "-v", "validate", "MyModlet"]).unwrap_err();
+        assert_eq!(clap::error::ErrorKind::ArgumentConflict, err.kind());
+
+        assert!(
+            Cli::try_parse_from(["7dmt", "-q", "validate", "MyModlet"])
+                .unwrap()
+                .quiet
+        );
+    }
+
+    #[test]
+    fn diff_missing_modlet() {
+        use super::{execute, Cli, CliError, Parser};
+
+        let cli = Cli::parse_from(["7dmt", "diff", "missing/modlet", "tests/fixtures/ModletA"]);
+        let result = execute(&cli).unwrap();
+
+        assert!(matches!(result.errors[..], [CliError::NoModletPath]));
+    }
+
+    #[test]
+    fn changelog_missing_modlet() {
+        use super::{execute, Cli, CliError, Parser};
+
+        let cli = Cli::parse_from(["7dmt", "changelog", "missing/modlet", "tests/fixtures/ModletA"]);
+        let result = execute(&cli).unwrap();
+
+        assert!(matches!(result.errors[..], [CliError::NoModletPath]));
+    }
+
+    #[test]
+    fn count_only_fails_without_modinfo() {
+        use super::{execute, Cli, CliError, Parser};
+
+        let cli = Cli::parse_from([
+            "7dmt",
+            "validate",
+            "--count-only",
+            "tests/fixtures/ModletA",
+            "tests/fixtures/NoModinfo",
+        ]);
+        let result = execute(&cli).unwrap();
+
+        // Any error makes the process exit with a failure
+        assert!(matches!(result.errors[..], [CliError::ModletsFailedValidation(1)]));
+    }
+
+    #[test]
+    fn package_load_failure() {
+        use super::{execute, Cli, CliError, Parser};
+
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let cli = Cli::parse_from([
+            "7dmt",
+            "package",
+            "tests/fixtures/Unloadable",
+            "--output",
+            output.to_str().unwrap(),
+        ]);
+        let result = execute(&cli).unwrap();
+
+        assert!(matches!(
+            result.errors[..],
+            [CliError::PackageFailed(1), CliError::LoadFailed(..)]
+        ));
+    }
+
+    #[test]
+    fn bash_completions() {
+        let script = super::completions(clap_complete::Shell::Bash).unwrap();
+
+        assert!(script.contains("7dmt"));
+        assert!(script.contains("validate"));
+    }
+
+    #[test]
+    fn bump_label_ordering() {
+        use super::{commands::bump::BumpOptions, Cli, Commands, Parser};
+
+        let cli = Cli::parse_from(["7dmt", "bump", "MyModlet", "--pre", "beta", "--clear-build", "--minor"]);
+        let Commands::Bump { vers, .. } = cli.command else {
+            panic!("expected the bump command");
+        };
+
+        assert_eq!(
+            vec![
+                BumpOptions::Minor,
+                BumpOptions::Build(String::new()),
+                BumpOptions::Pre(String::from("beta"))
+            ],
+            vers.bump_options()
+        );
+    }
 }
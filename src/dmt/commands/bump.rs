@@ -1,19 +1,55 @@
 use modinfo::ModinfoError;
-use std::path::Path;
+use serde::Serialize;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum BumpOptions {
     Major,
     Minor,
     Patch,
+    PreReleaseBump,
+    /// Set the pre-release label (an empty label clears it)
+    Pre(String),
+    /// Set the build metadata (an empty label clears it)
+    Build(String),
     Set(String),
     Verbosity(u8),
+    /// Compute the new version without writing it
+    DryRun,
 }
 
-pub fn run(modlet: impl AsRef<Path>, opts: Vec<BumpOptions>) -> Result<String, String> {
+/// The outcome of bumping a single modlet's version
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BumpReport {
+    pub path: PathBuf,
+    pub old_version: String,
+    pub new_version: String,
+    /// True if the new version was not written
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+impl fmt::Display for BumpReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} version of {} from {} to {}",
+            if self.dry_run { "Would bump" } else { "Bumped" },
+            self.path.display(),
+            self.old_version,
+            self.new_version
+        )
+    }
+}
+
+pub fn run(modlet: impl AsRef<Path>, opts: Vec<BumpOptions>) -> Result<BumpReport, String> {
     // dbg!(opts);
 
     let mut verbosity = 0;
+    let mut dry_run = false;
     let mut modinfo = match modinfo::parse(modlet.as_ref()) {
         Ok(result) => result,
         Err(err) => {
@@ -32,9 +68,23 @@ pub fn run(modlet: impl AsRef<Path>, opts: Vec<BumpOptions>) -> Result<String, S
             BumpOptions::Major => modinfo.bump_version_major(),
             BumpOptions::Minor => modinfo.bump_version_minor(),
             BumpOptions::Patch => modinfo.bump_version_patch(),
+            BumpOptions::PreReleaseBump => {
+                let pre = modinfo.get_version().pre.to_string();
+                if pre.is_empty() {
+                    return Err(format!(
+                        "{} has no pre-release version to bump",
+                        modlet.as_ref().display()
+                    ));
+                }
+
+                modinfo.add_version_pre(&next_pre_release(&pre));
+            }
+            BumpOptions::Pre(pre) => modinfo.add_version_pre(&pre),
+            BumpOptions::Build(build) => modinfo.add_version_build(&build),
             BumpOptions::Verbosity(some) => {
                 verbosity = some;
             }
+            BumpOptions::DryRun => dry_run = true,
         }
     }
 
@@ -42,13 +92,96 @@ pub fn run(modlet: impl AsRef<Path>, opts: Vec<BumpOptions>) -> Result<String, S
         dbg!(&modinfo);
     }
 
+    let report = BumpReport {
+        path: modlet.as_ref().to_path_buf(),
+        old_version: old_ver,
+        new_version: modinfo.get_version().to_string(),
+        dry_run,
+    };
+
+    if dry_run {
+        return Ok(report);
+    }
+
     match &modinfo.write(None) {
-        Ok(_) => Ok(format!(
-            "Bumped version of {} from {} to {}",
-            modlet.as_ref().display(),
-            old_ver,
-            modinfo.get_version(),
-        )),
+        Ok(_) => Ok(report),
         Err(err) => Err(format!("{}", err)),
     }
 }
+
+/// Increments the numeric counter of a pre-release (`alpha.1` -> `alpha.2`), appending `.1` if it has none
+fn next_pre_release(pre: &str) -> String {
+    let (label, counter) = match pre.rsplit_once('.') {
+        Some((label, counter)) => (Some(label), counter),
+        None => (None, pre),
+    };
+
+    match (label, counter.parse::<u64>()) {
+        (Some(label), Ok(counter)) => format!("{label}.{}", counter + 1),
+        (None, Ok(counter)) => (counter + 1).to_string(),
+        (_, Err(_)) => format!("{pre}.1"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::fixture;
+    use rstest::rstest;
+    use std::fs;
+
+    #[rstest]
+    #[case::without_counter("alpha", "alpha.1")]
+    #[case::with_counter("alpha.1", "alpha.2")]
+    #[case::with_multi_digit_counter("rc.9", "rc.10")]
+    #[case::with_dotted_label("beta.x", "beta.x.1")]
+    #[case::with_numeric_only("1", "2")]
+    fn test_next_pre_release(#[case] pre: &str, #[case] expected: &str) {
+        assert_eq!(expected, next_pre_release(pre));
+    }
+
+    #[test]
+    fn test_report_json() {
+        let report = BumpReport {
+            path: PathBuf::from("MyModlet"),
+            old_version: String::from("1.0.0"),
+            new_version: String::from("1.0.1"),
+            dry_run: false,
+        };
+
+        assert_eq!(
+            serde_json::json!({
+                "path": "MyModlet",
+                "old_version": "1.0.0",
+                "new_version": "1.0.1",
+            }),
+            serde_json::to_value(&report).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::with_pre(vec![BumpOptions::Minor, BumpOptions::Pre(String::from("beta"))], "1.1.0-beta")]
+    #[case::with_build(vec![BumpOptions::Patch, BumpOptions::Build(String::from("b42"))], "1.0.1+b42")]
+    #[case::with_cleared_pre(vec![BumpOptions::Set(String::from("1.2.0-rc.1")), BumpOptions::Pre(String::new())], "1.2.0")]
+    fn test_labels(#[case] opts: Vec<BumpOptions>, #[case] expected: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ModInfo.xml");
+        fs::copy(fixture("ModletA/ModInfo.xml"), &path).unwrap();
+
+        assert_eq!(expected, run(&path, opts).unwrap().new_version);
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ModInfo.xml");
+        fs::copy(fixture("ModletA/ModInfo.xml"), &path).unwrap();
+        let original = fs::read_to_string(&path).unwrap();
+
+        let report = run(&path, vec![BumpOptions::Patch, BumpOptions::DryRun]).unwrap();
+
+        assert_eq!("1.0.1", report.new_version);
+        assert!(report.to_string().starts_with("Would bump version of"));
+        assert_eq!(original, fs::read_to_string(&path).unwrap());
+    }
+}
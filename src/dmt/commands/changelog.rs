@@ -0,0 +1,155 @@
+use super::diff::{self, FileDiff};
+use crate::dmt::SETTINGS;
+use modlet::modlet::Modlet;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// The changes between two versions of the same modlet
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Changelog {
+    pub name: String,
+    pub old_version: String,
+    pub new_version: String,
+    /// Config files (relative to `Config/`) which only exist in the new version
+    pub added_files: Vec<PathBuf>,
+    /// Config files (relative to `Config/`) which only exist in the old version
+    pub removed_files: Vec<PathBuf>,
+    /// Config files (relative to `Config/`) whose contents differ between the versions
+    pub changed_files: Vec<PathBuf>,
+    /// The command changes within each changed XML file
+    pub commands: Vec<FileDiff>,
+}
+
+impl fmt::Display for Changelog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.old_version == self.new_version {
+            writeln!(f, "# {} {}", self.name, self.new_version)?;
+        } else {
+            writeln!(f, "# {} {} -> {}", self.name, self.old_version, self.new_version)?;
+        }
+
+        for (heading, files) in [
+            ("Added", &self.added_files),
+            ("Removed", &self.removed_files),
+            ("Changed", &self.changed_files),
+        ] {
+            if !files.is_empty() {
+                writeln!(f, "\n## {heading}\n")?;
+                for file in files {
+                    writeln!(f, "- {}", file.display())?;
+                }
+            }
+        }
+
+        for diff in &self.commands {
+            writeln!(f, "\n### {}\n", diff.file.display())?;
+            for command in &diff.added {
+                writeln!(f, "- Added{}", code(command))?;
+            }
+            for command in &diff.removed {
+                writeln!(f, "- Removed{}", code(command))?;
+            }
+            for command in &diff.changed {
+                writeln!(f, "- Changed{} to{}", code(&command.old), code(&command.new))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a (pretty-printed) command as inline code, or as an indented code block if it spans multiple lines
+///
+/// The result includes the whitespace separating it from the preceding text.
+fn code(command: &str) -> String {
+    if command.contains('\n') {
+        format!("\n\n  ```xml\n  {}\n  ```\n", command.replace('\n', "\n  "))
+    } else {
+        format!(" `{command}`")
+    }
+}
+
+/// Builds the changelog between two versions (`old` and `new`) of the same modlet
+pub fn changelog(old: &Modlet, new: &Modlet) -> eyre::Result<Changelog> {
+    let old_files = config_files(old)?;
+    let new_files = config_files(new)?;
+
+    Ok(Changelog {
+        name: new.name().to_string(),
+        old_version: old.modinfo.get_version().to_string(),
+        new_version: new.modinfo.get_version().to_string(),
+        added_files: new_files
+            .keys()
+            .filter(|file| !old_files.contains_key(*file))
+            .cloned()
+            .collect(),
+        removed_files: old_files
+            .keys()
+            .filter(|file| !new_files.contains_key(*file))
+            .cloned()
+            .collect(),
+        changed_files: old_files
+            .iter()
+            .filter(|(file, content)| new_files.get(*file).is_some_and(|other| other != *content))
+            .map(|(file, _)| file.clone())
+            .collect(),
+        commands: diff::diff(old, new)?.files,
+    })
+}
+
+/// Reads every loaded config file (XML and otherwise), keyed by its path relative to `Config/`
+fn config_files(modlet: &Modlet) -> eyre::Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let config_root = modlet.config_root();
+    let mut files = BTreeMap::new();
+
+    for xml in &modlet.xmls {
+        files.insert(xml.filename().into_owned(), fs::read(config_root.join(xml.filename()))?);
+    }
+    for file in modlet.files.iter().flatten() {
+        files.insert(file.strip_prefix(&config_root)?.to_path_buf(), fs::read(file)?);
+    }
+
+    Ok(files)
+}
+
+/// Loads two versions of a modlet and builds the changelog between them
+///
+/// # Arguments
+///
+/// * `old` - The path to the older version of the modlet
+/// * `new` - The path to the newer version of the modlet
+///
+pub fn run(old: &Path, new: &Path) -> eyre::Result<Changelog> {
+    let options = SETTINGS.read().unwrap().modlet_options();
+
+    changelog(
+        &Modlet::with_options(old, &options)?,
+        &Modlet::with_options(new, &options)?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::fixture;
+
+    #[test]
+    fn test_changelog() {
+        let changelog = run(&fixture("ModletA"), &fixture("ModletAFork")).unwrap();
+
+        assert_eq!("1.0.0", changelog.old_version);
+        assert_eq!("1.1.0", changelog.new_version);
+        assert_eq!(
+            vec![PathBuf::from("Localization.txt"), PathBuf::from("progression.xml")],
+            changelog.added_files
+        );
+        assert!(changelog.removed_files.is_empty());
+        assert_eq!(vec![PathBuf::from("items.xml")], changelog.changed_files);
+        assert_eq!(1, changelog.commands.len());
+        assert!(changelog.to_string().starts_with("# ModletAFork 1.0.0 -> 1.1.0\n"));
+    }
+}
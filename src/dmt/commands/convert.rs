@@ -1,15 +1,83 @@
-use crate::cli::RequestedVersion;
+use crate::{cli::RequestedVersion, dmt::SETTINGS};
 use modinfo::ModinfoError as Error;
-use std::path::Path;
+use modlet::modlet::find_modinfo;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-pub fn run(path: impl AsRef<Path>, requested_version: Option<&RequestedVersion>) -> Result<(), Error> {
+/// Converts the ModInfo at `path` (a ModInfo file or a modlet directory) to the requested version
+///
+/// If `backup` is set, the existing file is first copied to `<file>.bak` (or `<file>.bak.1`, `<file>.bak.2`, ... if
+/// that already exists).
+pub fn run(path: impl AsRef<Path>, requested_version: Option<&RequestedVersion>, backup: bool) -> Result<(), Error> {
     let modinfo_version = super::requested_version_to_modinfo_version(requested_version);
-    let mut modinfo = modinfo::parse(path)?;
+    let mut modinfo = modinfo::parse(path.as_ref())?;
 
     if modinfo.get_modinfo_version() == modinfo_version {
         Ok(())
     } else {
+        if backup {
+            backup_file(&modinfo_file(path.as_ref()))?;
+        }
         modinfo.set_modinfo_version(modinfo_version);
         modinfo.write(None)
     }
 }
+
+/// Resolves the ModInfo file of `path`, which may be the file itself or its modlet directory
+fn modinfo_file(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        let filename = SETTINGS.read().unwrap().modlet_options().modinfo_filename().to_string();
+        find_modinfo(path, &filename).unwrap_or_else(|| path.join(filename))
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Copies `file` to the first of `<file>.bak`, `<file>.bak.1`, `<file>.bak.2`, ... which doesn't exist yet
+fn backup_file(file: &Path) -> std::io::Result<PathBuf> {
+    let mut backup = PathBuf::from(format!("{}.bak", file.display()));
+    let mut index = 0;
+
+    while backup.exists() {
+        index += 1;
+        backup = PathBuf::from(format!("{}.bak.{index}", file.display()));
+    }
+    fs::copy(file, &backup)?;
+
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::fixture;
+
+    #[test]
+    fn test_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ModInfo.xml");
+        fs::copy(fixture("ModletA/ModInfo.xml"), &path).unwrap();
+        let original = fs::read_to_string(&path).unwrap();
+
+        run(&path, Some(&RequestedVersion::from(1)), true).unwrap();
+        assert_eq!(
+            original,
+            fs::read_to_string(dir.path().join("ModInfo.xml.bak")).unwrap()
+        );
+        assert_ne!(original, fs::read_to_string(&path).unwrap());
+
+        // An existing backup is never overwritten
+        let converted = fs::read_to_string(&path).unwrap();
+        run(&path, Some(&RequestedVersion::from(2)), true).unwrap();
+        assert_eq!(
+            original,
+            fs::read_to_string(dir.path().join("ModInfo.xml.bak")).unwrap()
+        );
+        assert_eq!(
+            converted,
+            fs::read_to_string(dir.path().join("ModInfo.xml.bak.1")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::dmt::SETTINGS;
+use modlet::modlet::Modlet;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// A command whose xpath matched on both sides, but whose instructions differ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangedCommand {
+    pub old: String,
+    pub new: String,
+}
+
+/// The differences between the two versions of a single XML file
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct FileDiff {
+    pub file: PathBuf,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedCommand>,
+}
+
+impl FileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// The differences between two versions of a modlet
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ModletDiff {
+    /// XML files which only exist in the first modlet
+    pub only_in_a: Vec<PathBuf>,
+    /// XML files which only exist in the second modlet
+    pub only_in_b: Vec<PathBuf>,
+    /// XML files which exist in both modlets, but whose commands differ
+    pub files: Vec<FileDiff>,
+}
+
+impl ModletDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_a.is_empty() && self.only_in_b.is_empty() && self.files.is_empty()
+    }
+}
+
+impl fmt::Display for ModletDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "No differences found");
+        }
+
+        for file in &self.only_in_a {
+            writeln!(f, "Only in A: {}", file.display())?;
+        }
+        for file in &self.only_in_b {
+            writeln!(f, "Only in B: {}", file.display())?;
+        }
+
+        for diff in &self.files {
+            writeln!(f, "\n{}", diff.file.display())?;
+            for command in &diff.removed {
+                writeln!(f, "  - {}", indent(command))?;
+            }
+            for command in &diff.added {
+                writeln!(f, "  + {}", indent(command))?;
+            }
+            for command in &diff.changed {
+                writeln!(f, "  ~ {}\n    {}", indent(&command.old), indent(&command.new))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Indents the continuation lines of a (pretty-printed) command to line up under its first line
+fn indent(command: &str) -> String {
+    command.replace('\n', "\n    ")
+}
+
+/// Compares the commands of two modlets, matching XML files by their path under `Config/`
+///
+/// Commands are matched by their type and xpath; matched commands with different instructions are reported as
+/// changed. Comments are ignored.
+pub fn diff(a: &Modlet, b: &Modlet) -> eyre::Result<ModletDiff> {
+    let files_a = commands_by_file(a);
+    let files_b = commands_by_file(b);
+    let mut result = ModletDiff::default();
+
+    for (file, commands_a) in &files_a {
+        let Some(commands_b) = files_b.get(file) else {
+            result.only_in_a.push(file.clone());
+            continue;
+        };
+
+        let mut diff = FileDiff {
+            file: file.clone(),
+            ..Default::default()
+        };
+        let mut unmatched_b = commands_b.iter().collect::<Vec<_>>();
+
+        for (key, rendered) in commands_a {
+            match unmatched_b.iter().position(|(other_key, _)| other_key == key) {
+                Some(index) => {
+                    let (_, other) = unmatched_b.remove(index);
+                    if rendered != other {
+                        diff.changed.push(ChangedCommand {
+                            old: rendered.clone(),
+                            new: other.clone(),
+                        });
+                    }
+                }
+                None => diff.removed.push(rendered.clone()),
+            }
+        }
+        diff.added = unmatched_b.into_iter().map(|(_, rendered)| rendered.clone()).collect();
+
+        if !diff.is_empty() {
+            result.files.push(diff);
+        }
+    }
+
+    let files_a = files_a.keys().collect::<BTreeSet<_>>();
+    result.only_in_b = files_b.into_keys().filter(|file| !files_a.contains(file)).collect();
+
+    Ok(result)
+}
+
+/// A command's identity (type and xpath) along with its rendered XML, grouped by file
+type FileCommands = BTreeMap<PathBuf, Vec<((&'static str, Vec<u8>), String)>>;
+
+fn commands_by_file(modlet: &Modlet) -> FileCommands {
+    let mut files = FileCommands::new();
+    for xml in &modlet.xmls {
+        let commands = files.entry(xml.filename().into_owned()).or_default();
+        for command in &xml.commands {
+            let Some(instruction) = command.instruction_set() else {
+                continue;
+            };
+
+            commands.push(((command.kind(), instruction.xpath.clone()), command.pretty()));
+        }
+    }
+
+    files
+}
+
+/// Loads two modlets and compares their commands
+///
+/// # Arguments
+///
+/// * `a` - The path to the first (e.g. upstream) modlet
+/// * `b` - The path to the second (e.g. forked) modlet
+///
+pub fn run(a: &Path, b: &Path) -> eyre::Result<ModletDiff> {
+    let options = SETTINGS.read().unwrap().modlet_options();
+
+    diff(&Modlet::with_options(a, &options)?, &Modlet::with_options(b, &options)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::load_fixture;
+
+    #[test]
+    fn test_diff() {
+        let diff = diff(&load_fixture("ModletA"), &load_fixture("ModletAFork")).unwrap();
+
+        assert!(diff.only_in_a.is_empty());
+        assert_eq!(vec![PathBuf::from("progression.xml")], diff.only_in_b);
+        assert_eq!(1, diff.files.len());
+
+        let items = &diff.files[0];
+        assert_eq!(PathBuf::from("items.xml"), items.file);
+        assert!(items.removed.is_empty());
+        assert_eq!(1, items.added.len());
+        assert!(items.added[0].starts_with(r#"<append xpath="/items">"#));
+        assert_eq!(1, items.changed.len());
+        assert!(items.changed[0].old.ends_with(">2</set>"));
+        assert!(items.changed[0].new.ends_with(">3</set>"));
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        assert!(diff(&load_fixture("ModletA"), &load_fixture("ModletA"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,23 @@
+use modlet::modlet::ModletXML;
+use std::path::Path;
+
+/// Parses a single modlet XML file and returns its commands in their `Debug` form, for diagnosing parser issues
+pub fn run(file: &Path) -> eyre::Result<String> {
+    let xml = ModletXML::new(file).load()?;
+
+    Ok(format!("{:#?}", xml.commands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::fixture;
+
+    #[test]
+    fn test_dump_ast() {
+        let dump = run(&fixture("ModletA/Config/items.xml")).unwrap();
+
+        assert!(dump.contains("Comment("), "{dump}");
+        assert!(dump.contains("Set("), "{dump}");
+    }
+}
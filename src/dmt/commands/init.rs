@@ -6,30 +6,49 @@ use std::{
     path::{Path, PathBuf},
 };
 
+/// A starter modlet XML, showing both a `set` and an `append`
+const EXAMPLE_XML: &str = r#"<configs>
+    <!-- Change a single value: make wood frames twice as sturdy -->
+    <set xpath="/blocks/block[@name='woodFrameMaster']/property[@name='MaxDamage']/@value">200</set>
+
+    <!-- Add new elements: give wood frames a custom tag -->
+    <append xpath="/blocks/block[@name='woodFrameMaster']">
+        <property name="CustomTag" value="example" />
+    </append>
+</configs>
+"#;
+
 struct ModletPaths {
     config: PathBuf,
+    example: PathBuf,
     modinfo: PathBuf,
     readme: PathBuf,
 }
 
 impl ModletPaths {
-    fn new(name: &str) -> Self {
-        let root = Path::new(".").join(name);
+    fn new(root: &Path) -> Self {
         let config = root.join("Config/.keep");
+        let example = root.join("Config/blocks.xml");
         let modinfo = root.join("ModInfo.xml");
         let readme = root.join("README.md");
 
         Self {
             config,
+            example,
             modinfo,
             readme,
         }
     }
 }
 
-pub fn run(name: impl ToString, requested_version: Option<&RequestedVersion>) -> Result<bool, ModinfoError> {
+pub fn run(
+    name: impl ToString,
+    requested_version: Option<&RequestedVersion>,
+    with_example: bool,
+) -> Result<bool, ModinfoError> {
     let name = name.to_string();
-    let modlet_paths = ModletPaths::new(&name);
+    let root = Path::new(".").join(&name);
+    let modlet_paths = ModletPaths::new(&root);
     if modlet_paths.modinfo.exists()
         && !Confirm::with_theme(&ColorfulTheme::default())
             .with_prompt(format!("Modlet {} already exists. Overwrite?", name))
@@ -40,12 +59,24 @@ pub fn run(name: impl ToString, requested_version: Option<&RequestedVersion>) ->
         return Ok(false);
     }
 
-    create(name, requested_version)
+    let created = create(&root, requested_version)?;
+    if with_example {
+        write_example(&root)?;
+    }
+
+    Ok(created)
 }
 
-pub fn create(name: impl ToString, requested_version: Option<&RequestedVersion>) -> Result<bool, ModinfoError> {
-    let name = name.to_string();
-    let modlet_paths = ModletPaths::new(&name);
+/// Writes a starter `Config/blocks.xml` into the modlet at the given path
+pub fn write_example(root: impl AsRef<Path>) -> Result<(), ModinfoError> {
+    Ok(fs::write(ModletPaths::new(root.as_ref()).example, EXAMPLE_XML)?)
+}
+
+/// Creates a new modlet at the given path, named after its final path component
+pub fn create(root: impl AsRef<Path>, requested_version: Option<&RequestedVersion>) -> Result<bool, ModinfoError> {
+    let root = root.as_ref();
+    let name = root.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let modlet_paths = ModletPaths::new(root);
     let modinfo_version = super::requested_version_to_modinfo_version(requested_version);
 
     fs::create_dir_all(modlet_paths.config)?;
@@ -60,3 +91,23 @@ pub fn create(name: impl ToString, requested_version: Option<&RequestedVersion>)
         Err(_) => Err(ModinfoError::WriteError),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modlet::modlet::Modlet;
+
+    #[test]
+    fn test_write_example() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("MyModlet");
+
+        create(&root, None).unwrap();
+        assert!(Modlet::new(&root).unwrap().xmls.is_empty());
+
+        write_example(&root).unwrap();
+        let counts = Modlet::new(&root).unwrap().command_counts();
+        assert_eq!(Some(&1), counts.get("set"));
+        assert_eq!(Some(&1), counts.get("append"));
+    }
+}
@@ -1,9 +1,13 @@
 use crate::cli::RequestedVersion;
 
 pub mod bump;
+pub mod changelog;
 pub mod convert;
+pub mod diff;
+pub mod dump_ast;
 pub mod init;
 pub mod package;
+pub mod validate;
 
 pub fn requested_version_to_modinfo_version(requested_version: Option<&RequestedVersion>) -> modinfo::ModinfoVersion {
     match requested_version {
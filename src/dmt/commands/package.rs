@@ -1,115 +1,394 @@
-use crate::dmt::{commands, SETTINGS};
+use crate::dmt::{
+    cli::{KeepComments, RequestedVersion},
+    commands,
+    helpers::{thread_pool, truncate_middle},
+    SETTINGS,
+};
 use color_eyre::eyre::eyre;
 use console::{style, Term};
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use modlet::modlet::Modlet;
-use quick_xml::{
-    events::{BytesEnd, BytesStart, BytesText, Event},
-    Writer,
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use modlet::modlet::{
+    bundle_xml, clear_config_dir, file_map, find_modinfo, read_ignore_file, LineEnding, LocalizationHeader, Modlet,
+    WriteOptions, DEFAULT_IO_RETRIES,
 };
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{btree_map, BTreeMap},
+    collections::BTreeMap,
     fs::{self, File},
+    io,
     path::{Path, PathBuf},
 };
+use thiserror::Error;
+
+// The widest a progress label may get before it is truncated, so long file paths don't wrap the terminal
+const MAX_LABEL_WIDTH: usize = 48;
 
 /// Reads a modlet's xml files
 fn load(path: impl AsRef<Path>, padding: usize, pb: &ProgressBar) -> eyre::Result<Modlet> {
     let path = path.as_ref().canonicalize().unwrap_or_default();
     let file_name = path.file_name().unwrap_or_default().to_str().unwrap();
     let verbose = SETTINGS.read().unwrap().verbosity > 0;
+    let options = SETTINGS.read().unwrap().modlet_options();
     if verbose {
         pb.set_prefix(format!("Loading {file_name:.<padding$}"));
     }
 
-    let config_dir = path.join("Config");
-    if !(config_dir.exists() && config_dir.is_dir()) {
-        return Err(eyre!(
-            "Invalid Modlet {}: Config directory does not exist",
-            config_dir.display()
-        ));
-    }
-
-    let modlet = Modlet::new(path)?;
-
-    Ok(modlet)
+    Ok(Modlet::with_options(path, &options)?)
 }
 
+/// Bundles `file` from each of `modlets`, reporting progress on `pb`
 fn package(
     file: &Path,
     modlets: Vec<&Modlet>,
     output_modlet: &Path,
+    write_options: &WriteOptions,
     padding: usize,
     pb: &ProgressBar,
 ) -> eyre::Result<()> {
-    let verbose = SETTINGS.read().unwrap().verbosity > 0;
-    let config_dir = output_modlet.join("Config");
-    let config_file = config_dir.join(file);
+    if SETTINGS.read().unwrap().verbosity > 0 {
+        let label = truncate_middle(&file.display().to_string(), padding.saturating_sub(1)).to_string();
+        pb.set_prefix(format!("Packaging {label:.<padding$}"));
+        pb.inc(modlets.len() as u64);
+    }
 
-    if config_file.exists() {
-        fs::remove_file(&config_file)?;
-    } else {
-        fs::create_dir_all(config_file.parent().unwrap())?;
-    };
+    Ok(bundle_xml(file, &modlets, output_modlet, write_options)?)
+}
 
-    let config_file = File::create(&config_file)?;
-    let mut writer = Writer::new_with_indent(&config_file, b' ', 4);
+/// The filename of the manifest written into the output modlet
+pub const MANIFEST_FILENAME: &str = "dmt-manifest.json";
+
+/// Records which modlets (and which of their files) were packaged into an output modlet
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The version of 7dmt that packaged the modlets
+    pub tool_version: String,
+    /// The packaged modlets, in packaging order
+    pub modlets: Vec<ManifestModlet>,
+    /// Each packaged config file, with the names of the modlets which contributed to it
+    pub files: BTreeMap<PathBuf, Vec<String>>,
+    /// A content hash of each file written into the output's Config directory (relative to the output modlet), used
+    /// to detect hand edits before the next package run; absent in manifests written by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hashes: Option<BTreeMap<PathBuf, String>>,
+}
 
-    writer.write_event(Event::Start(BytesStart::new("bundle")))?;
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestModlet {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+}
 
-    if verbose {
-        pb.set_prefix(format!("Packaging {:.<padding$}", file.display()));
+impl Manifest {
+    /// Builds the manifest for the given (sorted) modlets
+    pub fn new(modlets: &[Modlet]) -> Self {
+        Self {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            modlets: modlets
+                .iter()
+                .map(|modlet| ManifestModlet {
+                    name: modlet.name().to_string(),
+                    version: modlet.modinfo.get_version().to_string(),
+                    path: modlet.path.clone(),
+                })
+                .collect(),
+            files: file_map(modlets)
+                .into_iter()
+                .map(|(file, modlets)| (file, modlets.iter().map(|modlet| modlet.name().to_string()).collect()))
+                .collect(),
+            hashes: None,
+        }
+    }
+
+    /// Reads the manifest of the output modlet at `path`, if it has a readable one
+    pub fn read(path: &Path) -> Option<Self> {
+        fs::read_to_string(path.join(MANIFEST_FILENAME))
+            .ok()
+            .and_then(|manifest| serde_json::from_str(&manifest).ok())
     }
 
-    for modlet in modlets {
-        if verbose {
-            pb.inc(1);
+    /// Records the hash of every file currently in the Config directory of the output modlet at `path`
+    pub fn record_hashes(&mut self, path: &Path) -> eyre::Result<()> {
+        let mut hashes = BTreeMap::new();
+        for file in config_files(path)? {
+            let hash = file_hash(&file)?;
+            hashes.insert(file.strip_prefix(path).unwrap_or(&file).to_path_buf(), hash);
         }
+        self.hashes = Some(hashes);
 
-        // Inject a comment to indicate which modlet the xml came from
-        writer.write_event(Event::Comment(BytesText::new(
-            format!(" Included from {} ", modlet.name()).as_str(),
-        )))?;
+        Ok(())
+    }
 
-        modlet.write_xmls(&mut writer, file)?;
+    /// Writes the manifest into the output modlet at `path`
+    pub fn write(&self, path: &Path) -> eyre::Result<()> {
+        Ok(fs::write(
+            path.join(MANIFEST_FILENAME),
+            serde_json::to_string_pretty(self)?,
+        )?)
     }
+}
 
-    Ok(writer.write_event(Event::End(BytesEnd::new("bundle")))?)
+/// Sorts modlets by their declared priority, then by name to ensure consistent packaging
+fn sort_modlets(modlets: &mut [Modlet]) {
+    modlets.sort_by(|a, b| {
+        a.config
+            .priority
+            .cmp(&b.config.priority)
+            .then_with(|| a.name().cmp(&b.name()))
+    });
 }
 
-fn file_map(modlets: &[Modlet]) -> BTreeMap<PathBuf, Vec<&Modlet>> {
-    let mut files = BTreeMap::<PathBuf, Vec<&Modlet>>::new();
-    for modlet in modlets {
-        for file in modlet.xml_files() {
-            let file = file.as_ref().to_owned();
-            if let btree_map::Entry::Vacant(e) = files.entry(file.clone()) {
-                e.insert(vec![modlet]);
-            } else {
-                files.get_mut(&file).unwrap().push(modlet);
-            }
+/// The modlets which failed to load (with why), in which case nothing is packaged
+#[derive(Debug, Error)]
+#[error("{} modlet(s) failed to package", .0.len())]
+pub struct LoadFailures(pub Vec<(PathBuf, String)>);
+
+/// Options controlling how modlets are packaged
+#[derive(Debug)]
+pub struct PackageOptions {
+    /// The number of threads used to load and parse the modlets (default: one per CPU)
+    pub jobs: Option<usize>,
+    /// The number of threads used to write packaged files (default: `jobs`)
+    pub parallel_files: Option<usize>,
+    /// The ModInfo.xml version (1 or 2) of the output modlet (default: V2, or unchanged if it already exists)
+    pub output_modinfo_version: Option<u8>,
+    /// How many times a failed file copy is retried before giving up
+    pub io_retries: u32,
+    /// Which comments to keep in the packaged XML files
+    pub keep_comments: KeepComments,
+    /// Only (re)write the manifest, leaving the rest of the output modlet untouched
+    pub manifest_only: bool,
+    /// Also compress the output modlet into `<output>.zip` once it has been written
+    pub zip: bool,
+    /// How the header of each localization file appended to an earlier modlet's is handled
+    pub localization_header: LocalizationHeader,
+    /// The line ending of lines appended to an earlier modlet's localization file
+    pub localization_line_ending: LineEnding,
+    /// A modlet whose ModInfo is used as the base of the output modlet's (keeping the output's own name)
+    pub seed_from: Option<PathBuf>,
+    /// Replace the output's Config directory even if it has files which a previous package run didn't write
+    pub force: bool,
+}
+
+impl Default for PackageOptions {
+    fn default() -> Self {
+        Self {
+            jobs: None,
+            parallel_files: None,
+            output_modinfo_version: None,
+            io_retries: DEFAULT_IO_RETRIES,
+            keep_comments: KeepComments::default(),
+            manifest_only: false,
+            zip: false,
+            localization_header: LocalizationHeader::default(),
+            localization_line_ending: LineEnding::default(),
+            seed_from: None,
+            force: false,
         }
     }
-
-    files
 }
 
 /// Packages one or more modlets into a single modlet
 ///
+/// If `output_modlet` has a `.zip` extension, the modlet is packaged into a temporary directory and then compressed
+/// into that archive (as `ModletName/...`). Otherwise, with `options.zip`, the output modlet is written as usual
+/// and then also compressed into `<output_modlet>.zip`.
+///
 /// # Arguments
 ///
 /// * `modlets` - A list of modlet(s) to package
-/// * `modlet` - The path to the modlet to package into
+/// * `modlet` - The path to the modlet (or `.zip` archive) to package into
+/// * `options` - Options controlling how the modlets are packaged
 ///
 /// # Errors
 ///
 /// * If the game directory is invalid
 /// * If the modlet path is invalid
 ///
-pub fn run(modlets: &[PathBuf], output_modlet: &Path) -> eyre::Result<()> {
+pub fn run(modlets: &[PathBuf], output_modlet: &Path, options: &PackageOptions) -> eyre::Result<()> {
+    if !output_modlet
+        .extension()
+        .unwrap_or_default()
+        .eq_ignore_ascii_case("zip")
+    {
+        package_modlets(modlets, output_modlet, options, &|_| ())?;
+
+        if options.zip && output_modlet.exists() {
+            let mut archive = output_modlet.as_os_str().to_owned();
+            archive.push(".zip");
+            write_zip(output_modlet, Path::new(&archive))?;
+        }
+
+        return Ok(());
+    }
+
+    if options.manifest_only {
+        return Err(eyre!("--manifest-only cannot be used with a zip archive output"));
+    }
+
+    let staging = tempfile::tempdir()?;
+    let staged_modlet = staging.path().join(output_modlet.file_stem().unwrap_or_default());
+    package_modlets(modlets, &staged_modlet, options, &|_| ())?;
+
+    write_zip(&staged_modlet, output_modlet)
+}
+
+/// Compresses the modlet directory at `modlet` into a zip archive at `archive`, keeping the modlet's directory name
+fn write_zip(modlet: &Path, archive: &Path) -> eyre::Result<()> {
+    let root = modlet.parent().unwrap_or(modlet);
+    let mut entries = glob::glob(modlet.join("**/*").to_str().unwrap())?.collect::<Result<Vec<_>, _>>()?;
+    entries.push(modlet.to_path_buf());
+    entries.sort();
+
+    let mut zip = zip::ZipWriter::new(File::create(archive)?);
+    let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for entry in entries {
+        // Zip entry names always use forward slashes
+        let name = entry
+            .strip_prefix(root)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if entry.is_dir() {
+            zip.add_directory(name, options)?;
+        } else {
+            zip.start_file(name, options)?;
+            io::copy(&mut File::open(&entry)?, &mut zip)?;
+        }
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+/// Returns the files in the output modlet's Config directory which were not written by a previous package run
+///
+/// A file is attributed to a previous run if its content still matches the hash recorded in the output's manifest.
+/// Output from before hashes were recorded falls back to the "Included from" markers for XML files, and for other
+/// files to whether one of `modlets` has the same file.
+fn unattributed_files(output_modlet: &Path, modlets: &[Modlet]) -> eyre::Result<Vec<PathBuf>> {
+    let hashes = Manifest::read(output_modlet).and_then(|manifest| manifest.hashes);
+    let mut unattributed = Vec::new();
+
+    for file in config_files(output_modlet)? {
+        let relative = file.strip_prefix(output_modlet).unwrap_or(&file);
+        let attributed = match hashes.as_ref() {
+            Some(hashes) => hashes
+                .get(relative)
+                .is_some_and(|hash| file_hash(&file).is_ok_and(|current| &current == hash)),
+            None if file.extension().unwrap_or_default().eq_ignore_ascii_case("xml") => {
+                fs::read_to_string(&file).is_ok_and(|xml| xml.contains("<!-- Included from "))
+            }
+            None => modlets.iter().any(|modlet| modlet.path.join(relative).is_file()),
+        };
+
+        if !attributed {
+            unattributed.push(relative.to_path_buf());
+        }
+    }
+
+    Ok(unattributed)
+}
+
+/// Returns every file in the output modlet's Config directory
+fn config_files(output_modlet: &Path) -> eyre::Result<Vec<PathBuf>> {
+    let config_dir = output_modlet.join("Config");
+    if !config_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for file in glob::glob(config_dir.join("**/*").to_str().unwrap())? {
+        let file = file?;
+        if !file.is_dir() {
+            files.push(file);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Returns a hash of the file's content, as hex
+fn file_hash(path: &Path) -> io::Result<String> {
+    Ok(format!("{:08x}", crc32fast::hash(&fs::read(path)?)))
+}
+
+/// Returns the path of the output modlet's ModInfo, found case-insensitively by the configured filename
+///
+/// If the output has no ModInfo yet, this is where one with the configured filename belongs.
+fn output_modinfo_path(output_modlet: &Path) -> PathBuf {
+    let options = SETTINGS.read().unwrap().modlet_options();
+
+    find_modinfo(output_modlet, options.modinfo_filename())
+        .unwrap_or_else(|| output_modlet.join(options.modinfo_filename()))
+}
+
+/// Replaces the output modlet's ModInfo with the one from the `seed` modlet, renamed after the output modlet
+fn seed_modinfo(seed: &Path, output_modlet: &Path, requested_version: Option<&RequestedVersion>) -> eyre::Result<()> {
+    let options = SETTINGS.read().unwrap().modlet_options();
+    let seed_file = find_modinfo(seed, options.modinfo_filename()).ok_or_else(|| {
+        eyre!(
+            "Cannot seed from {}: it has no {}",
+            seed.display(),
+            options.modinfo_filename()
+        )
+    })?;
+    let name = output_modlet.file_name().unwrap_or_default().to_string_lossy();
+
+    let mut modinfo = modinfo::parse(seed_file)?;
+    modinfo.set_value_for("name", &name);
+    modinfo.set_value_for("display_name", &name);
+    if requested_version.is_some() {
+        modinfo.set_modinfo_version(commands::requested_version_to_modinfo_version(requested_version));
+    }
+
+    Ok(modinfo.write(Some(&output_modinfo_path(output_modlet)))?)
+}
+
+/// Warns about unrecognised (e.g. misspelled) commands, which are packaged verbatim and likely ignored by the game
+fn unknown_command_warnings(modlets: &[Modlet]) -> Vec<String> {
+    modlets
+        .iter()
+        .flat_map(|modlet| {
+            modlet.xmls.iter().flat_map(move |xml| {
+                xml.commands
+                    .iter()
+                    .filter(|command| command.as_ref() == "unknown")
+                    .map(move |command| {
+                        format!(
+                            "Warning: {}: {}: unknown command <{command}> is packaged as-is",
+                            modlet.name(),
+                            xml.filename().display()
+                        )
+                    })
+            })
+        })
+        .collect()
+}
+
+/// Loads and packages the modlets, each phase on its own thread pool (see `PackageOptions::jobs`)
+///
+/// `on_task` is called with the phase (`"load"` or `"write"`) at the start of each task, on the thread running it.
+fn package_modlets(
+    modlets: &[PathBuf],
+    output_modlet: &Path,
+    options: &PackageOptions,
+    on_task: &(dyn Fn(&'static str) + Sync),
+) -> eyre::Result<()> {
     let verbose = SETTINGS.read().unwrap().verbosity > 0;
+    let load_pool = thread_pool(options.jobs)?;
+    let write_pool = thread_pool(options.parallel_files.or(options.jobs))?;
     let modlet_count = modlets.len() as u64;
-    let mp = MultiProgress::new();
+    let quiet = SETTINGS.read().unwrap().quiet;
+    let mp = if quiet {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
     let spinner_style = ProgressStyle::with_template("{prefix:.cyan.bright} {spinner} {wide_msg}")
         .unwrap()
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
@@ -120,7 +399,6 @@ pub fn run(modlets: &[PathBuf], output_modlet: &Path) -> eyre::Result<()> {
         .unwrap_or(0)
         + 3;
     let term = Term::stdout();
-    let config_dir = output_modlet.join("Config");
     let output_modlet_name = output_modlet.file_name().unwrap().to_str().unwrap();
     if padding < output_modlet_name.len() {
         padding = output_modlet_name.len() + 3;
@@ -140,81 +418,151 @@ pub fn run(modlets: &[PathBuf], output_modlet: &Path) -> eyre::Result<()> {
     }
 
     // Using `par_iter()` to parallelize the packaging of each modlet.
-    let mut loaded_modlets: Vec<Modlet> = modlets
-        .par_iter()
-        .fold(Vec::<Modlet>::new, |mut vf, path| {
-            let pb = mp.add(ProgressBar::new(modlet_count));
-            pb.set_style(spinner_style.clone());
-
-            match load(path, padding, &pb) {
-                Ok(modlet) => {
-                    if verbose {
-                        pb.finish_with_message(style("OKAY").green().bold().to_string());
+    let results = load_pool.install(|| {
+        modlets
+            .par_iter()
+            .map(|path| {
+                on_task("load");
+                let pb = mp.add(ProgressBar::new(modlet_count));
+                pb.set_style(spinner_style.clone());
+
+                match load(path, padding, &pb) {
+                    Ok(modlet) => {
+                        if verbose {
+                            pb.finish_with_message(style("OKAY").green().bold().to_string());
+                        }
+                        Ok(modlet)
                     }
-                    vf.push(modlet);
-                }
 
-                Err(err) => {
-                    if verbose {
-                        pb.finish_with_message(format!(
-                            "{} {}",
-                            style("FAIL").red().bold(),
-                            style(format!("({err})")).red()
-                        ));
+                    Err(err) => {
+                        if verbose {
+                            pb.finish_with_message(format!(
+                                "{} {}",
+                                style("FAIL").red().bold(),
+                                style(format!("({err})")).red()
+                            ));
+                        }
+                        Err((path.clone(), err.to_string()))
                     }
                 }
-            }
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut loaded_modlets = Vec::new();
+    let mut failures = Vec::new();
+    for result in results {
+        match result {
+            Ok(modlet) => loaded_modlets.push(modlet),
+            Err(failure) => failures.push(failure),
+        }
+    }
+    // Nothing is packaged unless every modlet loaded
+    if !failures.is_empty() {
+        return Err(LoadFailures(failures).into());
+    }
 
-            vf
-        })
-        .reduce(Vec::<Modlet>::new, |mut vf, mut v| {
-            vf.append(&mut v);
-            vf
-        });
-
-    if (loaded_modlets.len() as u64) == modlet_count {
-        // Create the output modlet if necessary
-        if !output_modlet.exists() {
-            commands::init::create(output_modlet_name, None)?;
+    sort_modlets(&mut loaded_modlets);
+    let mut manifest = Manifest::new(&loaded_modlets);
+
+    if !quiet {
+        for warning in unknown_command_warnings(&loaded_modlets) {
+            term.write_line(style(warning).yellow().to_string().as_ref())?;
         }
+    }
 
-        if config_dir.exists() {
-            if config_dir.is_dir() {
-                fs::remove_dir_all(&config_dir)?;
-            } else {
-                return Err(eyre!(
-                    "Invalid Modlet {}: Config directory is not a directory",
-                    config_dir.display()
-                ));
-            }
+    if options.manifest_only {
+        fs::create_dir_all(output_modlet)?;
+        // The config files are left untouched, so are their recorded hashes
+        manifest.hashes = Manifest::read(output_modlet).and_then(|existing| existing.hashes);
+        manifest.write(output_modlet)?;
+        if !quiet {
+            term.write_line(
+                style(format!(
+                    "\n\nManifest of {modlet_count} modlet(s) written to {}\n",
+                    output_modlet.join(MANIFEST_FILENAME).display()
+                ))
+                .green()
+                .to_string()
+                .as_ref(),
+            )?;
+        }
+
+        return Ok(());
+    }
+
+    if !options.force {
+        let unattributed = unattributed_files(output_modlet, &loaded_modlets)?;
+        if !unattributed.is_empty() {
+            return Err(eyre!(
+                "{} has files which were not written by a previous package run (use --force to replace them): {}",
+                output_modlet.display(),
+                unattributed
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
         }
+    }
 
-        // Sort modlets by name to ensure consistent packaging
-        loaded_modlets.sort_by(|a, b| a.name().cmp(&b.name()));
+    let requested_version = options.output_modinfo_version.map(RequestedVersion::from);
 
-        let modlets = loaded_modlets.clone();
-        let files = file_map(&modlets);
-        let files_count = files.len() as u64;
+    // Create the output modlet if necessary
+    if !output_modlet.exists() {
+        commands::init::create(output_modlet, requested_version.as_ref())?;
+    } else if requested_version.is_some() {
+        let modinfo_path = output_modinfo_path(output_modlet);
+        let mut modinfo = modinfo::parse(&modinfo_path)?;
+        modinfo.set_modinfo_version(commands::requested_version_to_modinfo_version(
+            requested_version.as_ref(),
+        ));
+        modinfo.write(Some(&modinfo_path))?;
+    }
+
+    if let Some(seed) = options.seed_from.as_ref() {
+        seed_modinfo(seed, output_modlet, requested_version.as_ref())?;
+    }
 
-        if config_dir.exists() {
-            if config_dir.is_dir() {
-                fs::remove_dir_all(&config_dir)?;
-            } else {
-                return Err(eyre!(
-                    "Invalid Modlet {}: Config directory is not a directory",
-                    config_dir.display()
+    let write_options = WriteOptions::new()
+        .with_io_retries(options.io_retries)
+        .with_on_retry({
+            let (mp, retries) = (mp.clone(), options.io_retries);
+            move |path, err, attempt| {
+                let _ = mp.println(format!(
+                    "Copying {} failed ({err}), retrying ({attempt}/{retries})...",
+                    path.display()
                 ));
             }
-        }
-
-        // Write XML files
+        })
+        .with_include_comments(options.keep_comments == KeepComments::All)
+        .with_include_sources(options.keep_comments != KeepComments::None)
+        .with_localization_header(options.localization_header)
+        .with_localization_line_ending(options.localization_line_ending)
+        .with_ignore(read_ignore_file(output_modlet)?);
+    let modlets = loaded_modlets.clone();
+    let mut files = file_map(&modlets);
+    files.retain(|file, _| !write_options.is_ignored(file));
+    let files_count = files.len() as u64;
+    // Fit labels to the longest file path (truncating overlong ones), keeping them aligned with the modlet names
+    let file_padding = files
+        .keys()
+        .map(|file| file.display().to_string().chars().count() + 1)
+        .fold(padding - 2, usize::max)
+        .min(MAX_LABEL_WIDTH.max(padding - 2));
+
+    clear_config_dir(output_modlet)?;
+
+    // Write XML files
+    write_pool.install(|| {
         files
             .into_par_iter()
             .try_for_each(|(file, modlets)| -> eyre::Result<()> {
+                on_task("write");
                 let pb = mp.add(ProgressBar::new(files_count));
                 pb.set_style(spinner_style.clone());
 
-                match package(&file, modlets, output_modlet, padding - 2, &pb) {
+                match package(&file, modlets, output_modlet, &write_options, file_padding, &pb) {
                     Ok(_) => {
                         if verbose {
                             pb.finish_with_message(style("OKAY").green().bold().to_string());
@@ -232,26 +580,34 @@ pub fn run(modlets: &[PathBuf], output_modlet: &Path) -> eyre::Result<()> {
                 }
 
                 Ok(())
-            })?;
+            })
+    })?;
 
-        // Write other files
-        let pb = mp.add(ProgressBar::new(1));
-        pb.set_style(spinner_style.clone());
+    // Write other files
+    let pb = mp.add(ProgressBar::new(1));
+    pb.set_style(spinner_style.clone());
 
-        if verbose {
-            let padding = padding - 2;
-            pb.set_prefix(format!("Packaging {:.<padding$}", "additional files"));
-        }
+    if verbose {
+        let padding = file_padding;
+        pb.set_prefix(format!("Packaging {:.<padding$}", "additional files"));
+    }
 
+    write_pool.install(|| -> eyre::Result<()> {
         for modlet in loaded_modlets {
             if verbose {
                 pb.inc(1);
             }
 
-            modlet.write_files(output_modlet)?;
+            modlet.write_files_with(output_modlet, &write_options)?;
         }
-        pb.finish_with_message(style("OKAY").green().bold().to_string());
 
+        Ok(())
+    })?;
+    pb.finish_with_message(style("OKAY").green().bold().to_string());
+    manifest.record_hashes(output_modlet)?;
+    manifest.write(output_modlet)?;
+
+    if !quiet {
         term.write_line(
             style(format!(
                 "\n\n{modlet_count} modlet(s) successfully packaged into {}\n",
@@ -261,17 +617,335 @@ pub fn run(modlets: &[PathBuf], output_modlet: &Path) -> eyre::Result<()> {
             .to_string()
             .as_ref(),
         )?;
-    } else {
-        term.write_line(
-            style(format!(
-                "\n\n{count} modlet(s) failed to package!\n",
-                count = modlet_count - (loaded_modlets.len() as u64)
-            ))
-            .red()
-            .to_string()
-            .as_ref(),
-        )?;
     }
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::fixture;
+    use modinfo::ModinfoVersion;
+    use rstest::rstest;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_phase_pools() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let options = PackageOptions {
+            jobs: Some(1),
+            parallel_files: Some(3),
+            ..Default::default()
+        };
+        // The size of the pool each task ran on
+        let probes = Mutex::new(Vec::new());
+        let on_task = |phase| probes.lock().unwrap().push((phase, rayon::current_num_threads()));
+        package_modlets(&[fixture("ModletA"), fixture("ModletB")], &output, &options, &on_task).unwrap();
+
+        let probes = probes.into_inner().unwrap();
+        let threads = |phase| {
+            probes
+                .iter()
+                .filter(|(probed, _)| *probed == phase)
+                .map(|(_, threads)| *threads)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![1, 1], threads("load"));
+        assert_eq!(vec![3, 3], threads("write"));
+    }
+
+    // The number of ModInfo files (of any case) in the modlet at `path`
+    fn modinfo_files(path: &Path) -> usize {
+        fs::read_dir(path)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .eq_ignore_ascii_case(modlet::modlet::MODINFO_FILENAME)
+            })
+            .count()
+    }
+
+    #[rstest]
+    #[case::with_v1(1, ModinfoVersion::V1)]
+    #[case::with_v2(2, ModinfoVersion::V2)]
+    fn test_output_modinfo_version(#[case] version: u8, #[case] expected: ModinfoVersion) {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let options = PackageOptions {
+            output_modinfo_version: Some(version),
+            ..Default::default()
+        };
+
+        run(&[fixture("ModletA"), fixture("ModletB")], &output, &options).unwrap();
+
+        let modinfo = modinfo::parse(output.join("ModInfo.xml")).unwrap();
+        assert_eq!(expected, modinfo.get_modinfo_version());
+    }
+
+    #[test]
+    fn test_existing_lowercase_modinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let modlets = [fixture("ModletA"), fixture("ModletB")];
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+        fs::rename(output.join("ModInfo.xml"), output.join("modinfo.xml")).unwrap();
+
+        let options = PackageOptions {
+            output_modinfo_version: Some(1),
+            ..Default::default()
+        };
+        run(&modlets, &output, &options).unwrap();
+
+        let modinfo = modinfo::parse(output.join("modinfo.xml")).unwrap();
+        assert_eq!(ModinfoVersion::V1, modinfo.get_modinfo_version());
+        assert_eq!(1, modinfo_files(&output));
+    }
+
+    #[test]
+    fn test_seed_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let options = PackageOptions {
+            seed_from: Some(fixture("ModletB")),
+            ..Default::default()
+        };
+
+        run(&[fixture("ModletA"), fixture("ModletB")], &output, &options).unwrap();
+
+        let modinfo = modinfo::parse(output.join("ModInfo.xml")).unwrap();
+        let value = |field| modinfo.get_value_for(field).map(|value| value.to_string());
+        assert_eq!(Some(String::from("Packaged")), value("name"));
+        assert_eq!(Some(String::from("DonovanMods")), value("author"));
+        assert_eq!(Some(String::from("The second fixture modlet")), value("description"));
+        assert_eq!("2.1.0", modinfo.get_version().to_string());
+    }
+
+    #[test]
+    fn test_seed_into_lowercase_modinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let modlets = [fixture("ModletA"), fixture("ModletB")];
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+        fs::rename(output.join("ModInfo.xml"), output.join("modinfo.xml")).unwrap();
+
+        let options = PackageOptions {
+            seed_from: Some(fixture("ModletB")),
+            ..Default::default()
+        };
+        run(&modlets, &output, &options).unwrap();
+
+        let modinfo = modinfo::parse(output.join("modinfo.xml")).unwrap();
+        assert_eq!("2.1.0", modinfo.get_version().to_string());
+        assert_eq!(1, modinfo_files(&output));
+    }
+
+    #[rstest]
+    #[case::with_all(KeepComments::All, true, true)]
+    #[case::with_section(KeepComments::Section, true, false)]
+    #[case::with_none(KeepComments::None, false, false)]
+    fn test_keep_comments(#[case] keep_comments: KeepComments, #[case] markers: bool, #[case] source: bool) {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let options = PackageOptions {
+            keep_comments,
+            ..Default::default()
+        };
+
+        run(&[fixture("ModletA"), fixture("ModletB")], &output, &options).unwrap();
+
+        let items = fs::read_to_string(output.join("Config/items.xml")).unwrap();
+        assert_eq!(markers, items.contains("<!-- Included from ModletA -->"));
+        assert_eq!(source, items.contains("<!-- Make pistols lighter -->"));
+    }
+
+    #[test]
+    fn test_priority_ordering() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+
+        run(
+            &[fixture("ModletA"), fixture("ModletB")],
+            &output,
+            &PackageOptions::default(),
+        )
+        .unwrap();
+
+        // ModletB declares a lower priority, so it is bundled first despite its name
+        let items = fs::read_to_string(output.join("Config/items.xml")).unwrap();
+        let position = |name: &str| items.find(&format!("Included from {name}")).unwrap();
+        assert!(position("ModletB") < position("ModletA"));
+    }
+
+    #[test]
+    fn test_manifest_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let existing = output.join("Config/items.xml");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, "<configs />").unwrap();
+        let options = PackageOptions {
+            manifest_only: true,
+            ..Default::default()
+        };
+
+        run(&[fixture("ModletA"), fixture("ModletB")], &output, &options).unwrap();
+
+        let mut entries = fs::read_dir(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(vec!["Config", MANIFEST_FILENAME], entries);
+        assert_eq!("<configs />", fs::read_to_string(existing).unwrap());
+
+        let manifest: Manifest =
+            serde_json::from_str(&fs::read_to_string(output.join(MANIFEST_FILENAME)).unwrap()).unwrap();
+        let names = manifest
+            .modlets
+            .iter()
+            .map(|modlet| modlet.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["ModletB", "ModletA"], names);
+        assert_eq!(vec!["ModletB", "ModletA"], manifest.files[Path::new("items.xml")]);
+    }
+
+    #[test]
+    fn test_hand_edited_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let modlets = [fixture("ModletA"), fixture("ModletB")];
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+
+        // Repackaging over our own output is always fine
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+
+        fs::write(output.join("Config/custom.xml"), "<configs />").unwrap();
+        let err = run(&modlets, &output, &PackageOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("custom.xml"), "{err}");
+        assert!(output.join("Config/custom.xml").is_file());
+
+        let options = PackageOptions {
+            force: true,
+            ..Default::default()
+        };
+        run(&modlets, &output, &options).unwrap();
+        assert!(!output.join("Config/custom.xml").exists());
+    }
+
+    #[test]
+    fn test_hand_edited_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let modlets = [fixture("ModletA"), fixture("ModletB")];
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+
+        let items = output.join("Config/items.xml");
+        let edited = fs::read_to_string(&items)
+            .unwrap()
+            .replace("</bundle>", "<remove xpath=\"/items/item\" /></bundle>");
+        fs::write(&items, edited).unwrap();
+
+        let err = run(&modlets, &output, &PackageOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("items.xml"), "{err}");
+    }
+
+    #[test]
+    fn test_legacy_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+        let modlets = [fixture("ModletA"), fixture("ModletB")];
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+
+        // Output from before manifests were written is recognised by its "Included from" markers
+        fs::remove_file(output.join(MANIFEST_FILENAME)).unwrap();
+        run(&modlets, &output, &PackageOptions::default()).unwrap();
+
+        fs::remove_file(output.join(MANIFEST_FILENAME)).unwrap();
+        fs::write(output.join("Config/custom.xml"), "<configs />").unwrap();
+        let err = run(&modlets, &output, &PackageOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("custom.xml"), "{err}");
+        assert!(!err.to_string().contains("items.xml"), "{err}");
+    }
+
+    #[test]
+    fn test_load_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Packaged");
+
+        let err = run(
+            &[fixture("ModletA"), fixture("Unloadable")],
+            &output,
+            &PackageOptions::default(),
+        )
+        .unwrap_err();
+        let LoadFailures(failures) = err.downcast::<LoadFailures>().unwrap();
+
+        assert_eq!(1, failures.len());
+        assert!(failures[0].0.ends_with("Unloadable"));
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_zip_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("Bundle.zip");
+
+        run(
+            &[fixture("ModletA"), fixture("ModletB")],
+            &archive,
+            &PackageOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                "Bundle/",
+                "Bundle/Config/",
+                "Bundle/Config/Localization.txt",
+                "Bundle/Config/blocks.xml",
+                "Bundle/Config/items.xml",
+                "Bundle/ModInfo.xml",
+                "Bundle/README.md",
+                "Bundle/dmt-manifest.json",
+            ],
+            zip_entries(&archive)
+        );
+        assert!(!dir.path().join("Bundle").exists());
+    }
+
+    #[test]
+    fn test_zip_flag() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("Bundle");
+        let options = PackageOptions {
+            zip: true,
+            ..Default::default()
+        };
+
+        run(&[fixture("ModletA")], &output, &options).unwrap();
+
+        assert!(output.join("ModInfo.xml").is_file());
+        assert_eq!(
+            vec![
+                "Bundle/",
+                "Bundle/Config/",
+                "Bundle/Config/items.xml",
+                "Bundle/ModInfo.xml",
+                "Bundle/README.md",
+                "Bundle/dmt-manifest.json",
+            ],
+            zip_entries(&dir.path().join("Bundle.zip"))
+        );
+    }
+
+    fn zip_entries(archive: &Path) -> Vec<String> {
+        let zip = zip::ZipArchive::new(File::open(archive).unwrap()).unwrap();
+        let mut names = zip.file_names().map(String::from).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
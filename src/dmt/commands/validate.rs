@@ -0,0 +1,341 @@
+use crate::dmt::{helpers::thread_pool, SETTINGS};
+use console::{style, Term};
+use modlet::modlet::{Check, Modlet, Problem, Severity, ValidationOptions};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The version of the `--report` JSON schema; bumped whenever the schema changes incompatibly
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Options controlling how modlets are validated
+#[derive(Debug)]
+pub struct ValidateOptions {
+    /// Apply safe automatic fixes before validating
+    pub fix: bool,
+    /// Write a JSON report of every modlet's problems to this file
+    pub report: Option<PathBuf>,
+    /// Validate modlets in parallel (serially, in input order, when false)
+    pub parallel: bool,
+    /// Report relative xpaths (not starting with `/`) as errors
+    pub strict_xpath: bool,
+    /// Report tags which are not known modlet commands as errors
+    pub strict: bool,
+    /// Only print how many modlets passed and failed, instead of each modlet's results
+    pub count_only: bool,
+}
+
+impl Default for ValidateOptions {
+    fn default() -> Self {
+        Self {
+            fix: false,
+            report: None,
+            parallel: true,
+            strict_xpath: false,
+            strict: false,
+            count_only: false,
+        }
+    }
+}
+
+/// The validation outcome of a single modlet
+#[derive(Debug)]
+pub struct ValidationReport {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+    pub fixes: Vec<String>,
+    /// The number of checks which found no problems
+    pub checks_passed: usize,
+    /// The number of checks which found at least one problem
+    pub checks_failed: usize,
+}
+
+impl ValidationReport {
+    /// Returns the name of the validated modlet
+    pub fn name(&self) -> String {
+        self.path.file_name().unwrap_or_default().to_string_lossy().to_string()
+    }
+
+    /// Returns true if any problem is severe enough to fail validation
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|problem| problem.severity == Severity::Error)
+    }
+
+    /// Returns the overall status of the modlet (`okay`, `warn` or `fail`)
+    pub fn status(&self) -> &'static str {
+        if self.has_errors() {
+            "fail"
+        } else if self.problems.is_empty() {
+            "okay"
+        } else {
+            "warn"
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    schema_version: u32,
+    modlets: Vec<JsonModlet<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonModlet<'a> {
+    name: String,
+    path: &'a Path,
+    status: &'static str,
+    checks_passed: usize,
+    checks_failed: usize,
+    fixes: &'a [String],
+    problems: Vec<JsonProblem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonProblem<'a> {
+    severity: String,
+    message: &'a str,
+}
+
+/// Writes the validation reports to `path` as JSON (see [`REPORT_SCHEMA_VERSION`])
+pub fn write_report(reports: &[ValidationReport], path: &Path) -> eyre::Result<()> {
+    let report = JsonReport {
+        schema_version: REPORT_SCHEMA_VERSION,
+        modlets: reports
+            .iter()
+            .map(|report| JsonModlet {
+                name: report.name(),
+                path: &report.path,
+                status: report.status(),
+                checks_passed: report.checks_passed,
+                checks_failed: report.checks_failed,
+                fixes: &report.fixes,
+                problems: report
+                    .problems
+                    .iter()
+                    .map(|problem| JsonProblem {
+                        severity: problem.severity.to_string(),
+                        message: &problem.message,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    };
+
+    Ok(fs::write(path, serde_json::to_string_pretty(&report)?)?)
+}
+
+/// Validates a single modlet, optionally fixing what it can first
+pub fn validate(path: &Path, options: &ValidateOptions) -> ValidationReport {
+    // A missing config directory is reported as a problem rather than failing to load
+    let modlet_options = SETTINGS
+        .read()
+        .unwrap()
+        .modlet_options()
+        .with_allow_missing_config(true)
+        .with_allow_invalid_xml(true);
+    let validation_options = ValidationOptions::new()
+        .with_strict_xpath(options.strict_xpath)
+        .with_strict_commands(options.strict);
+    let mut fixes = Vec::new();
+    let checks = match Modlet::with_options(path, &modlet_options) {
+        Ok(mut modlet) => match options.fix.then(|| modlet.fix()) {
+            Some(Err(err)) => vec![Check::new(
+                "fix",
+                vec![Problem::error(format!("Could not fix modlet: {err}"))],
+            )],
+            Some(Ok(applied)) => {
+                fixes = applied;
+                modlet.checks_with(&validation_options)
+            }
+            None => modlet.checks_with(&validation_options),
+        },
+        Err(err) => vec![Check::new(
+            "load",
+            vec![Problem::error(format!("Could not load modlet: {err}"))],
+        )],
+    };
+    let checks_passed = checks.iter().filter(|check| check.passed()).count();
+
+    ValidationReport {
+        path: path.to_path_buf(),
+        checks_passed,
+        checks_failed: checks.len() - checks_passed,
+        problems: checks.into_iter().flat_map(|check| check.problems).collect(),
+        fixes,
+    }
+}
+
+/// Validates one or more modlets, reporting any problems found
+///
+/// # Arguments
+///
+/// * `modlets` - A list of modlet(s) to validate
+/// * `options` - Options controlling how the modlets are validated
+///
+pub fn run(modlets: &[PathBuf], options: &ValidateOptions) -> eyre::Result<Vec<ValidationReport>> {
+    let term = Term::stdout();
+    let jobs = if options.parallel {
+        SETTINGS.read().unwrap().jobs
+    } else {
+        Some(1)
+    };
+    // Reports are collected (in input order) before anything is printed, so output never interleaves
+    let reports = thread_pool(jobs)?.install(|| {
+        modlets
+            .par_iter()
+            .map(|path| validate(path, options))
+            .collect::<Vec<_>>()
+    });
+
+    // Failures are still reported (as errors) by the caller
+    if !SETTINGS.read().unwrap().quiet {
+        for line in render(&reports, options.count_only) {
+            term.write_line(&line)?;
+        }
+    }
+
+    if let Some(path) = options.report.as_ref() {
+        write_report(&reports, path)?;
+    }
+
+    Ok(reports)
+}
+
+/// Returns the number of modlets which failed validation
+pub fn failed_count(reports: &[ValidationReport]) -> usize {
+    reports.iter().filter(|report| report.has_errors()).count()
+}
+
+/// Formats the reports for the console, in the order given
+///
+/// With `count_only`, only a plain `N passed, M failed` line is returned.
+fn render(reports: &[ValidationReport], count_only: bool) -> Vec<String> {
+    let failed = failed_count(reports);
+    if count_only {
+        return vec![format!("{} passed, {failed} failed", reports.len() - failed)];
+    }
+
+    let mut lines = Vec::new();
+    let padding = reports.iter().map(|report| report.name().len()).max().unwrap_or(0) + 3;
+
+    for report in reports {
+        let status = report.status().to_uppercase();
+        let status = match report.status() {
+            "fail" => style(status).red().bold(),
+            "okay" => style(status).green().bold(),
+            _ => style(status).yellow().bold(),
+        };
+
+        lines.push(format!(
+            "{:.<padding$} {status} ({}/{} checks passed)",
+            style(report.name()).cyan().bright(),
+            report.checks_passed,
+            report.checks_passed + report.checks_failed
+        ));
+
+        for fix in &report.fixes {
+            lines.push(style(format!("    fixed: {fix}")).green().to_string());
+        }
+
+        for problem in &report.problems {
+            let line = format!("    {problem}");
+            let line = match problem.severity {
+                Severity::Error => style(line).red(),
+                Severity::Warning => style(line).yellow(),
+            };
+
+            lines.push(line.to_string());
+        }
+    }
+
+    lines.push(format!(
+        "\n{} modlet(s) validated: {} passed, {}",
+        reports.len(),
+        reports.len() - failed,
+        if failed > 0 {
+            style(format!("{failed} failed")).red().to_string()
+        } else {
+            format!("{failed} failed")
+        }
+    ));
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::fixture;
+    use rstest::rstest;
+
+    #[test]
+    fn test_write_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.json");
+        let reports = [
+            ValidationReport {
+                path: PathBuf::from("Mods/Good"),
+                problems: Vec::new(),
+                fixes: Vec::new(),
+                checks_passed: 7,
+                checks_failed: 0,
+            },
+            ValidationReport {
+                path: PathBuf::from("Mods/Bad"),
+                problems: vec![Problem::error("ModInfo.xml does not exist")],
+                fixes: vec![String::from("Renamed config to Config")],
+                checks_passed: 6,
+                checks_failed: 1,
+            },
+        ];
+
+        write_report(&reports, &path).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        let keys = json.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(vec!["modlets", "schema_version"], keys);
+        assert_eq!(REPORT_SCHEMA_VERSION, json["schema_version"].as_u64().unwrap() as u32);
+        assert_eq!("okay", json["modlets"][0]["status"]);
+        assert_eq!("fail", json["modlets"][1]["status"]);
+        assert_eq!("error", json["modlets"][1]["problems"][0]["severity"]);
+        assert_eq!(1, json["modlets"][1]["checks_failed"]);
+    }
+
+    #[rstest]
+    #[case::in_parallel(true)]
+    #[case::serially(false)]
+    fn test_ordered_output(#[case] parallel: bool) {
+        let modlets = [fixture("ModletB"), fixture("ModletA"), fixture("Missing")];
+        let options = ValidateOptions {
+            parallel,
+            ..Default::default()
+        };
+
+        let reports = run(&modlets, &options).unwrap();
+        let names = render(&reports, false)
+            .iter()
+            .map(|line| console::strip_ansi_codes(line).to_string())
+            .filter(|line| line.contains("checks passed"))
+            .map(|line| line.split('.').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(vec!["ModletB", "ModletA", "Missing"], names);
+    }
+
+    #[test]
+    fn test_count_only() {
+        let modlets = [fixture("ModletB"), fixture("ModletA"), fixture("NoModinfo")];
+        let options = ValidateOptions {
+            count_only: true,
+            ..Default::default()
+        };
+
+        let reports = run(&modlets, &options).unwrap();
+
+        assert_eq!(vec!["2 passed, 1 failed"], render(&reports, true));
+        assert_eq!(1, failed_count(&reports));
+    }
+}
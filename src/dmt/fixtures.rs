@@ -0,0 +1,25 @@
+//! Helpers for loading the test fixtures in `tests/fixtures`
+
+use modlet::modlet::Modlet;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Returns the path of the named fixture (e.g. `ModletA` or `ModletA/ModInfo.xml`)
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+/// Loads the named fixture modlet
+pub fn load_fixture(name: &str) -> Modlet {
+    Modlet::new(fixture(name)).unwrap()
+}
+
+/// Creates a minimal modlet in `dir`, named after the directory, whose ModInfo is written as `modinfo_name`
+pub fn temp_modlet(dir: &Path, modinfo_name: &str) {
+    let name = dir.file_name().unwrap().to_string_lossy();
+
+    fs::create_dir_all(dir.join("Config")).unwrap();
+    fs::write(dir.join(modinfo_name), format!("<xml><Name value=\"{name}\" /></xml>")).unwrap();
+}
@@ -1,35 +1,312 @@
+use crate::dmt::SETTINGS;
 use eyre::{eyre, Result};
-use rayon::prelude::*;
-use std::path::{Path, PathBuf};
+use modlet::modlet::find_modinfo;
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 pub fn verify_modlet_path(path: impl AsRef<Path>) -> Option<PathBuf> {
-    let path = path
-        .as_ref()
-        .canonicalize()
-        .expect("Failed to canonicalize path {path:?}");
+    let path = path.as_ref().canonicalize().ok()?;
+    let options = SETTINGS.read().unwrap().modlet_options();
 
-    if path.exists() && path.is_dir() && path.join("modinfo.xml").exists() {
+    if path.exists() && path.is_dir() && find_modinfo(&path, options.modinfo_filename()).is_some() {
         Some(path)
     } else {
         None
     }
 }
 
-pub fn verify_modlet_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
-    let verified_paths = paths
-        .par_iter()
-        .filter_map(verify_modlet_path)
-        .collect::<Vec<PathBuf>>();
-
-    if verified_paths.is_empty() {
-        let dirname = if paths[0].is_dir() {
-            paths[0].as_ref()
+/// Expands any glob patterns (e.g. `mods/*`) in the given paths, de-duplicating the results
+///
+/// Paths without glob characters are returned unchanged, whether or not they exist.
+pub fn expand_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if pattern.contains(['*', '?', '[']) {
+            for entry in glob::glob(&pattern)? {
+                expanded.push(entry?);
+            }
         } else {
-            paths[0].parent().unwrap()
+            expanded.push(path.clone());
+        }
+    }
+
+    let mut seen = HashSet::new();
+    expanded.retain(|path| seen.insert(path.clone()));
+
+    Ok(expanded)
+}
+
+/// Finds the modlets at, or up to `max_depth` directory levels below, each of the given paths
+///
+/// The directories inside a modlet are only searched when `flatten` is set, in which case any nested modlets are
+/// treated as independent modlets. Every modlet is returned once, by its canonical path.
+pub fn discover_modlets(paths: &[PathBuf], max_depth: usize, flatten: bool) -> Result<Vec<PathBuf>> {
+    let mut modlets = Vec::new();
+    for path in expand_paths(paths)? {
+        collect_modlets(&path, max_depth, flatten, &mut modlets);
+    }
+
+    let mut seen = HashSet::new();
+    modlets.retain(|path| seen.insert(path.clone()));
+
+    if modlets.is_empty() {
+        return Err(no_modlets_found(paths));
+    }
+
+    Ok(modlets)
+}
+
+fn collect_modlets(path: &Path, depth: usize, flatten: bool, modlets: &mut Vec<PathBuf>) {
+    let Ok(path) = path.canonicalize() else {
+        return;
+    };
+
+    if let Some(modlet) = verify_modlet_path(&path) {
+        modlets.push(modlet);
+        if !flatten {
+            return;
+        }
+    }
+
+    if depth > 0 {
+        let mut children = fs::read_dir(&path)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|child| child.is_dir() && !child.is_symlink())
+            .collect::<Vec<_>>();
+        children.sort();
+
+        for child in children {
+            collect_modlets(&child, depth - 1, flatten, modlets);
+        }
+    }
+}
+
+fn no_modlets_found(paths: &[PathBuf]) -> eyre::Report {
+    let dirname = if paths[0].is_dir() {
+        paths[0].as_ref()
+    } else {
+        paths[0].parent().unwrap()
+    };
+
+    eyre!("No valid modlets found in {}", dirname.display())
+}
+
+/// Removes any modlets whose directory name matches one of the excluded names
+pub fn exclude_modlets(paths: Vec<PathBuf>, excluded: &[String]) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default();
+            !excluded.iter().any(|excluded| name == excluded.as_str())
+        })
+        .collect()
+}
+
+/// Returns the files changed in the git repository containing `dir` since the commit `since` (including uncommitted
+/// and untracked files), or `None` if `dir` is not inside a git repository
+pub fn git_changed_files(dir: &Path, since: &str) -> Result<Option<Vec<PathBuf>>> {
+    let git = |args: &[&str]| Command::new("git").arg("-C").arg(dir).args(args).output();
+    let toplevel = match git(&["rev-parse", "--show-toplevel"]) {
+        Ok(output) if output.status.success() => {
+            PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()).canonicalize()?
+        }
+        // Not a git repository (or git is not installed)
+        _ => return Ok(None),
+    };
+
+    let mut files = Vec::new();
+    for args in [
+        &["diff", "--name-only", since][..],
+        &["ls-files", "--others", "--exclude-standard", "--full-name"],
+    ] {
+        let output = git(args)?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        files.extend(
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|file| toplevel.join(file)),
+        );
+    }
+
+    Ok(Some(files))
+}
+
+/// Keeps only the modlets containing at least one of the files returned by `changed_files` for that modlet
+///
+/// Modlets for which `changed_files` returns `None` (i.e. which are not in a git repository) are always kept.
+pub fn changed_modlets(
+    modlets: Vec<PathBuf>,
+    changed_files: impl Fn(&Path) -> Result<Option<Vec<PathBuf>>>,
+) -> Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for modlet in modlets {
+        match changed_files(&modlet)? {
+            Some(files) if !files.iter().any(|file| file.starts_with(&modlet)) => {}
+            _ => changed.push(modlet),
+        }
+    }
+
+    Ok(changed)
+}
+
+/// Builds a thread pool with the given number of threads (or rayon's default when `None`)
+pub fn thread_pool(threads: Option<usize>) -> Result<rayon::ThreadPool> {
+    let mut builder = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = threads {
+        builder = builder.num_threads(threads);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Shortens `text` to at most `width` characters by replacing its middle with an ellipsis
+pub fn truncate_middle(text: &str, width: usize) -> Cow<'_, str> {
+    let length = text.chars().count();
+    if length <= width {
+        return Cow::Borrowed(text);
+    }
+
+    let tail = width.saturating_sub(1) / 2;
+    let head = width.saturating_sub(1) - tail;
+    let chars = text.chars();
+
+    Cow::Owned(format!(
+        "{}…{}",
+        chars.clone().take(head).collect::<String>(),
+        chars.skip(length - tail).collect::<String>()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmt::fixtures::{fixture, temp_modlet};
+    use rayon::prelude::*;
+    use rstest::rstest;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_exclude_modlets() {
+        let paths = vec![
+            PathBuf::from("mods/ModletA"),
+            PathBuf::from("mods/ModletB"),
+            PathBuf::from("mods/ModletC"),
+        ];
+
+        assert_eq!(
+            vec![PathBuf::from("mods/ModletA"), PathBuf::from("mods/ModletC")],
+            exclude_modlets(paths, &[String::from("ModletB")])
+        );
+    }
+
+    #[rstest]
+    #[case::with_changes(Some(vec!["mods/ModletB/Config/items.xml", "README.md"]), vec!["mods/ModletB"])]
+    #[case::without_changes(Some(vec![]), vec![])]
+    #[case::outside_git(None, vec!["mods/ModletA", "mods/ModletB"])]
+    fn test_changed_modlets(#[case] changed_files: Option<Vec<&str>>, #[case] expected: Vec<&str>) {
+        let modlets = vec![PathBuf::from("mods/ModletA"), PathBuf::from("mods/ModletB")];
+        let changed_files = |_: &Path| {
+            Ok(changed_files
+                .clone()
+                .map(|files| files.iter().map(PathBuf::from).collect()))
         };
 
-        return Err(eyre!("No valid modlets found in {}", dirname.display()));
+        assert_eq!(
+            expected.iter().map(PathBuf::from).collect::<Vec<_>>(),
+            changed_modlets(modlets, changed_files).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_expand_paths() {
+        let paths = [fixture("*"), fixture("ModletA"), PathBuf::from("missing/modlet")];
+
+        assert_eq!(
+            vec![
+                fixture("ModletA"),
+                fixture("ModletAFork"),
+                fixture("ModletB"),
+                fixture("NoModinfo"),
+                fixture("Unloadable"),
+                PathBuf::from("missing/modlet"),
+            ],
+            expand_paths(&paths).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_verify_lowercase_modinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        temp_modlet(dir.path(), "modinfo.xml");
+
+        assert_eq!(Some(dir.path().canonicalize().unwrap()), verify_modlet_path(dir.path()));
+    }
+
+    #[rstest]
+    #[case::with_given_paths(false, 0, vec!["Parent", "Child"])]
+    #[case::with_discovery(false, 3, vec!["Parent", "Child"])]
+    #[case::with_flatten(true, 3, vec!["Parent", "Child", "Grandchild"])]
+    #[case::with_flatten_and_max_depth(true, 1, vec!["Parent", "Child"])]
+    fn test_discover_modlets(#[case] flatten: bool, #[case] max_depth: usize, #[case] expected: Vec<&str>) {
+        let dir = tempfile::tempdir().unwrap();
+        let parent = dir.path().join("Parent");
+        temp_modlet(&parent, "ModInfo.xml");
+        temp_modlet(&parent.join("Child"), "ModInfo.xml");
+        temp_modlet(&parent.join("Extras/Grandchild"), "ModInfo.xml");
+
+        // The child is passed explicitly as well as being nested, but must only be included once
+        let modlets = discover_modlets(&[parent.clone(), parent.join("Child")], max_depth, flatten).unwrap();
+        let names = modlets
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(expected, names);
+    }
+
+    #[rstest]
+    #[case::with_short_text("items.xml", 12, "items.xml")]
+    #[case::with_exact_width("items.xml", 9, "items.xml")]
+    #[case::with_long_path("XUi_Menu/windows/very/deeply/nested/windows.xml", 20, "XUi_Menu/w…ndows.xml")]
+    #[case::with_odd_width("abcdefghij", 5, "ab…ij")]
+    fn test_truncate_middle(#[case] text: &str, #[case] width: usize, #[case] expected: &str) {
+        let truncated = truncate_middle(text, width);
+
+        assert_eq!(expected, truncated);
+        assert!(truncated.chars().count() <= width);
     }
 
-    Ok(verified_paths)
+    #[rstest]
+    #[case::with_one_thread(1)]
+    #[case::with_two_threads(2)]
+    fn test_thread_pool(#[case] threads: usize) {
+        let pool = thread_pool(Some(threads)).unwrap();
+        let used_threads = pool.install(|| {
+            (0..64)
+                .into_par_iter()
+                .map(|_| {
+                    thread::sleep(Duration::from_millis(1));
+                    thread::current().id()
+                })
+                .collect::<HashSet<_>>()
+        });
+
+        assert_eq!(threads, pool.current_num_threads());
+        assert!(used_threads.len() <= threads);
+    }
 }
@@ -9,6 +9,7 @@ mod dmt;
 pub struct CommandResult {
     errors: Vec<cli::CliError>,
     messages: Vec<String>,
+    output: Vec<String>,
     verbose: u8,
 }
 
@@ -19,6 +20,10 @@ fn main() -> Result<()> {
     let stderr = Term::stderr();
     let result = cli::run()?;
 
+    for output in result.output {
+        stdout.write_line(&output)?;
+    }
+
     if result.errors.is_empty() {
         if result.verbose >= 1 {
             for message in result.messages {